@@ -4,9 +4,18 @@
 // }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Las migraciones se embeben con sqlx::migrate!, hay que recompilar si cambian
+    println!("cargo:rerun-if-changed=migrations");
     tonic_build::configure()
         .build_server(true)
         .build_client(true)
-        .compile(&["proto/users.proto"], &["proto/users"])?;
+        .compile(
+            &[
+                "proto/users.proto",
+                "proto/google/rpc/status.proto",
+                "proto/google/rpc/error_details.proto",
+            ],
+            &["proto"],
+        )?;
     Ok(())
 }
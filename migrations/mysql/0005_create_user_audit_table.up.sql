@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS user_audit (
+    id BIGINT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+    operation VARCHAR(16) NOT NULL,
+    user_id VARCHAR(48) NULL,
+    before_value TEXT NULL,
+    after_value TEXT NULL,
+    caller VARCHAR(256) NOT NULL,
+    created_at TIMESTAMP(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    INDEX user_audit_user_id_idx (user_id),
+    INDEX user_audit_created_at_idx (created_at)
+);
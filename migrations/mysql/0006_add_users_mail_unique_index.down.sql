@@ -0,0 +1,2 @@
+DROP INDEX users_mail_key ON users;
+ALTER TABLE users MODIFY mail VARCHAR(256) NOT NULL;
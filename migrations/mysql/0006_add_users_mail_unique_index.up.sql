@@ -0,0 +1,7 @@
+-- Solo se quitan los espacios, la politica de mails del servidor (lowercase,
+-- preserve, ...) se aplica a los mails ya guardados con migrate normalize-mails.
+-- Con utf8mb4_bin el indice distingue mayusculas igual que el servidor con preserve.
+-- Si quedan mails repetidos el indice no se puede crear y hay que resolverlos a mano
+UPDATE users SET mail = TRIM(mail);
+ALTER TABLE users MODIFY mail VARCHAR(256) CHARACTER SET utf8mb4 COLLATE utf8mb4_bin NOT NULL;
+CREATE UNIQUE INDEX users_mail_key ON users (mail);
@@ -0,0 +1 @@
+ALTER TABLE users ADD FULLTEXT INDEX users_name_fulltext (name);
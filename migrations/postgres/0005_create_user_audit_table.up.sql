@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS user_audit (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    operation VARCHAR(16) NOT NULL,
+    user_id VARCHAR(48) NULL,
+    before_value TEXT NULL,
+    after_value TEXT NULL,
+    caller VARCHAR(256) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS user_audit_user_id_idx ON user_audit (user_id);
+CREATE INDEX IF NOT EXISTS user_audit_created_at_idx ON user_audit (created_at);
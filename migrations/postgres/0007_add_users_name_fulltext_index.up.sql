@@ -0,0 +1,3 @@
+-- Se usa la configuracion 'simple' porque los nombres no tienen idioma: no se
+-- eliminan stopwords ni se reducen las palabras a su raiz
+CREATE INDEX users_name_fulltext ON users USING GIN (to_tsvector('simple', name));
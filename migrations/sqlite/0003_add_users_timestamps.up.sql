@@ -0,0 +1,5 @@
+-- SQLite no admite ADD COLUMN con un default no constante como CURRENT_TIMESTAMP,
+-- por eso las filas existentes se completan con un UPDATE
+ALTER TABLE users ADD COLUMN created_at DATETIME NOT NULL DEFAULT '1970-01-01 00:00:00';
+ALTER TABLE users ADD COLUMN updated_at DATETIME NOT NULL DEFAULT '1970-01-01 00:00:00';
+UPDATE users SET created_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP;
@@ -0,0 +1,5 @@
+-- Solo se quitan los espacios, la politica de mails del servidor (lowercase,
+-- preserve, ...) se aplica a los mails ya guardados con migrate normalize-mails.
+-- Si quedan mails repetidos el indice no se puede crear y hay que resolverlos a mano
+UPDATE users SET mail = TRIM(mail);
+CREATE UNIQUE INDEX users_mail_key ON users (mail);
@@ -0,0 +1,32 @@
+-- Tabla FTS5 con su propia copia de users.name. No se usa contenido externo porque
+-- se ligaria por el rowid implicito de users, que VACUUM puede renumerar. Cada
+-- usuario se liga a su fila de users_fts por users_fts_ids, indexada por id y por
+-- rowid, asi los triggers no recorren la tabla FTS en cada escritura
+CREATE TABLE users_fts_ids (
+    fts_rowid INTEGER PRIMARY KEY,
+    id VARCHAR(48) NOT NULL UNIQUE
+);
+CREATE VIRTUAL TABLE users_fts USING fts5(name);
+
+INSERT INTO users_fts_ids(id) SELECT id FROM users;
+INSERT INTO users_fts(rowid, name)
+    SELECT users_fts_ids.fts_rowid, users.name
+    FROM users JOIN users_fts_ids ON users_fts_ids.id = users.id;
+
+CREATE TRIGGER users_fts_insert AFTER INSERT ON users BEGIN
+    INSERT INTO users_fts_ids(id) VALUES (new.id);
+    INSERT INTO users_fts(rowid, name)
+        SELECT fts_rowid, new.name FROM users_fts_ids WHERE id = new.id;
+END;
+
+CREATE TRIGGER users_fts_delete AFTER DELETE ON users BEGIN
+    DELETE FROM users_fts
+        WHERE rowid = (SELECT fts_rowid FROM users_fts_ids WHERE id = old.id);
+    DELETE FROM users_fts_ids WHERE id = old.id;
+END;
+
+CREATE TRIGGER users_fts_update AFTER UPDATE OF id, name ON users BEGIN
+    UPDATE users_fts_ids SET id = new.id WHERE id = old.id;
+    UPDATE users_fts SET name = new.name
+        WHERE rowid = (SELECT fts_rowid FROM users_fts_ids WHERE id = new.id);
+END;
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::errors::ErrorKinsper;
+
+pub const AUTHORIZATION_METADATA_KEY: &str = "authorization";
+const BEARER_PREFIX: &str = "Bearer ";
+
+// Quien hizo el pedido segun su token. El interceptor la deja en las extensiones
+// del pedido para que la lean los handlers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthIdentity {
+    pub subject: String,
+    pub method: AuthMethod,
+    // Roles del claim roles del JWT o los configurados en la API key. La politica
+    // puede sumar otros por subject, ver authorization
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    ApiKey,
+    Jwt,
+}
+
+impl AuthIdentity {
+    pub fn from_request<T>(request: &Request<T>) -> Option<&AuthIdentity> {
+        request.extensions().get::<AuthIdentity>()
+    }
+}
+
+// API key en la configuracion, con el formato <nombre>:<key>[:<rol>[+<rol>...]]. El
+// nombre es la identidad que queda en la auditoria y los roles los que recibe al
+// autenticarse, ademas de los que le asigne el archivo de roles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    pub roles: Vec<String>,
+}
+
+// Separa los roles de una API key. La coma ya separa las keys en SERVER_API_KEYS
+const API_KEY_ROLE_SEPARATOR: char = '+';
+
+impl FromStr for ApiKey {
+    type Err = ErrorKinsper;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            ErrorKinsper::InvalidArgument(
+                "Invalid api key, expected <name>:<key>[:<role>[+<role>...]]".to_string(),
+            )
+        };
+        let mut parts = value.splitn(3, ':');
+        let (Some(name), Some(key)) = (parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let roles: Vec<String> = match parts.next() {
+            Some(roles) => roles
+                .split(API_KEY_ROLE_SEPARATOR)
+                .map(|role| role.to_string())
+                .collect(),
+            None => vec![],
+        };
+        if name.is_empty() || key.is_empty() || roles.iter().any(|role| role.is_empty()) {
+            return Err(invalid());
+        }
+        Ok(ApiKey {
+            name: name.to_string(),
+            key: key.to_string(),
+            roles,
+        })
+    }
+}
+
+impl ApiKey {
+    // Para mostrar en la configuracion sin exponer la key
+    pub fn redacted(&self) -> String {
+        if self.roles.is_empty() {
+            format!("{}:****", self.name)
+        } else {
+            let roles = self.roles.join(&API_KEY_ROLE_SEPARATOR.to_string());
+            format!("{}:****:{}", self.name, roles)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+// Valida el header authorization: Bearer <token> contra las API keys y las claves
+// de JWT configuradas. Sin ninguna configurada deja pasar todos los pedidos
+#[derive(Clone, Default)]
+pub struct Authenticator {
+    // token => API key
+    api_keys: Arc<HashMap<String, ApiKey>>,
+    jwt_keys: Arc<HashMap<Algorithm, DecodingKey>>,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_api_key(mut self, api_key: ApiKey) -> Self {
+        Arc::make_mut(&mut self.api_keys).insert(api_key.key.clone(), api_key);
+        self
+    }
+
+    pub fn with_hs256_secret(mut self, secret: &[u8]) -> Self {
+        Arc::make_mut(&mut self.jwt_keys)
+            .insert(Algorithm::HS256, DecodingKey::from_secret(secret));
+        self
+    }
+
+    pub fn with_rs256_public_key(mut self, pem: &[u8]) -> Result<Self, ErrorKinsper> {
+        let key = DecodingKey::from_rsa_pem(pem).map_err(|err| {
+            ErrorKinsper::InvalidArgument(format!("Invalid RS256 public key: {}", err))
+        })?;
+        Arc::make_mut(&mut self.jwt_keys).insert(Algorithm::RS256, key);
+        Ok(self)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || !self.jwt_keys.is_empty()
+    }
+
+    pub fn authenticate(&self, token: &str) -> Result<AuthIdentity, ErrorKinsper> {
+        if let Some(api_key) = self.api_key(token) {
+            return Ok(AuthIdentity {
+                subject: api_key.name.clone(),
+                method: AuthMethod::ApiKey,
+                roles: api_key.roles.clone(),
+            });
+        }
+
+        // Solo se aceptan los algoritmos que tienen una clave configurada, asi un
+        // token no puede elegir verificarse con otra
+        let invalid = || ErrorKinsper::Unauthenticated("Invalid token".to_string());
+        let header = decode_header(token).map_err(|_| invalid())?;
+        let key = self.jwt_keys.get(&header.alg).ok_or_else(invalid)?;
+        let mut validation = Validation::new(header.alg);
+        validation.validate_aud = false;
+        let claims = decode::<Claims>(token, key, &validation)
+            .map_err(|err| ErrorKinsper::Unauthenticated(format!("Invalid token: {}", err)))?
+            .claims;
+        Ok(AuthIdentity {
+            subject: claims.sub,
+            method: AuthMethod::Jwt,
+            roles: claims.roles,
+        })
+    }
+
+    // Compara contra todas las keys sin cortar en la primera diferencia
+    fn api_key(&self, token: &str) -> Option<&ApiKey> {
+        self.api_keys.iter().fold(None, |found, (key, api_key)| {
+            if constant_time_eq(key.as_bytes(), token.as_bytes()) {
+                Some(api_key)
+            } else {
+                found
+            }
+        })
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if !self.is_enabled() {
+            return Ok(request);
+        }
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION_METADATA_KEY)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER_PREFIX))
+            .ok_or_else(|| ErrorKinsper::Unauthenticated("Missing bearer token".to_string()))?;
+        let identity = self.authenticate(token.trim())?;
+        request.extensions_mut().insert(identity);
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod auth_tests {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+    use rsa::{RsaPrivateKey, RsaPublicKey};
+    use serde::Serialize;
+    use tonic::service::Interceptor;
+    use tonic::{Code, Request};
+
+    use crate::auth::{
+        ApiKey, AuthIdentity, AuthMethod, Authenticator, AUTHORIZATION_METADATA_KEY,
+    };
+
+    // Par de claves RSA generado en cada corrida, devuelve la privada y la publica en PEM
+    fn rsa_key_pair() -> (String, String) {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let public_key = RsaPublicKey::from(&private_key);
+        (
+            private_key
+                .to_pkcs8_pem(LineEnding::LF)
+                .unwrap()
+                .to_string(),
+            public_key.to_public_key_pem(LineEnding::LF).unwrap(),
+        )
+    }
+
+    #[derive(Serialize)]
+    struct Claims<'a> {
+        sub: &'a str,
+        exp: i64,
+        roles: Vec<&'a str>,
+    }
+
+    fn jwt(header: Header, key: &EncodingKey, expires_in: i64) -> String {
+        let claims = Claims {
+            sub: "alice",
+            exp: chrono::Utc::now().timestamp() + expires_in,
+            roles: vec!["operator"],
+        };
+        encode(&header, &claims, key).unwrap()
+    }
+
+    fn call(
+        authenticator: &Authenticator,
+        authorization: Option<&str>,
+    ) -> Result<Option<AuthIdentity>, Code> {
+        let mut request = Request::new(());
+        if let Some(authorization) = authorization {
+            request
+                .metadata_mut()
+                .insert(AUTHORIZATION_METADATA_KEY, authorization.parse().unwrap());
+        }
+        authenticator
+            .clone()
+            .call(request)
+            .map(|request| AuthIdentity::from_request(&request).cloned())
+            .map_err(|status| status.code())
+    }
+
+    #[test]
+    fn test01_when_no_credentials_configured_then_every_request_passes() {
+        let authenticator = Authenticator::new();
+
+        assert!(!authenticator.is_enabled());
+        assert_eq!(call(&authenticator, None), Ok(None));
+    }
+
+    #[test]
+    fn test02_when_api_key_then_only_known_keys_are_authenticated() {
+        let authenticator = Authenticator::new()
+            .with_api_key("admin:s3cret:admin+operator".parse().unwrap())
+            .with_api_key("ci-bot:ci-key".parse().unwrap());
+
+        assert_eq!(
+            call(&authenticator, Some("Bearer s3cret")),
+            Ok(Some(AuthIdentity {
+                subject: "admin".to_string(),
+                method: AuthMethod::ApiKey,
+                roles: vec!["admin".to_string(), "operator".to_string()],
+            }))
+        );
+        assert_eq!(
+            call(&authenticator, Some("Bearer ci-key")).map(|identity| identity.unwrap().roles),
+            Ok(vec![])
+        );
+        assert_eq!(call(&authenticator, None), Err(Code::Unauthenticated));
+        assert_eq!(
+            call(&authenticator, Some("Bearer other")),
+            Err(Code::Unauthenticated)
+        );
+        assert_eq!(
+            call(&authenticator, Some("s3cret")),
+            Err(Code::Unauthenticated)
+        );
+        for api_key in [
+            "admin",
+            ":s3cret",
+            "admin:",
+            "admin:s3cret:",
+            "admin:s3cret:admin+",
+        ] {
+            assert!(api_key.parse::<ApiKey>().is_err(), "{}", api_key);
+        }
+    }
+
+    #[test]
+    fn test03_when_hs256_jwt_then_validates_signature_and_expiration() {
+        let authenticator = Authenticator::new().with_hs256_secret(b"jwt-secret");
+        let key = EncodingKey::from_secret(b"jwt-secret");
+
+        let valid = jwt(Header::default(), &key, 60);
+        let identity = call(&authenticator, Some(&format!("Bearer {}", valid)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(identity.subject, "alice");
+        assert_eq!(identity.method, AuthMethod::Jwt);
+        assert_eq!(identity.roles, vec!["operator".to_string()]);
+
+        let expired = jwt(Header::default(), &key, -3600);
+        let other_secret = jwt(Header::default(), &EncodingKey::from_secret(b"other"), 60);
+        for token in [expired, other_secret] {
+            assert_eq!(
+                call(&authenticator, Some(&format!("Bearer {}", token))),
+                Err(Code::Unauthenticated)
+            );
+        }
+    }
+
+    #[test]
+    fn test04_when_rs256_jwt_then_only_configured_algorithms_are_accepted() {
+        let (private_key, public_key) = rsa_key_pair();
+        let authenticator = Authenticator::new()
+            .with_rs256_public_key(public_key.as_bytes())
+            .unwrap();
+        let rsa_key = EncodingKey::from_rsa_pem(private_key.as_bytes()).unwrap();
+
+        let valid = jwt(Header::new(jsonwebtoken::Algorithm::RS256), &rsa_key, 60);
+        assert_eq!(
+            call(&authenticator, Some(&format!("Bearer {}", valid)))
+                .unwrap()
+                .map(|identity| identity.subject),
+            Some("alice".to_string())
+        );
+
+        // Firmado con HS256 usando la clave publica como secreto
+        let confused = jwt(
+            Header::default(),
+            &EncodingKey::from_secret(public_key.as_bytes()),
+            60,
+        );
+        assert_eq!(
+            call(&authenticator, Some(&format!("Bearer {}", confused))),
+            Err(Code::Unauthenticated)
+        );
+        assert!(Authenticator::new()
+            .with_rs256_public_key(b"not a key")
+            .is_err());
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::auth::{ApiKey, AuthIdentity};
+use crate::errors::ErrorKinsper;
+
+// RPCs de UserService, con los nombres del .proto
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rpc {
+    GetUser,
+    GetUserByMail,
+    GetAllUsers,
+    SearchUsers,
+    CreateUser,
+    UpdateNameUser,
+    UpdateMailUser,
+    DeleteUser,
+    RestoreUser,
+    PurgeDeletedUsers,
+    ResetUserTable,
+    ListAuditEvents,
+    WatchUsers,
+}
+
+impl Rpc {
+    pub const ALL: [Rpc; 13] = [
+        Rpc::GetUser,
+        Rpc::GetUserByMail,
+        Rpc::GetAllUsers,
+        Rpc::SearchUsers,
+        Rpc::CreateUser,
+        Rpc::UpdateNameUser,
+        Rpc::UpdateMailUser,
+        Rpc::DeleteUser,
+        Rpc::RestoreUser,
+        Rpc::PurgeDeletedUsers,
+        Rpc::ResetUserTable,
+        Rpc::ListAuditEvents,
+        Rpc::WatchUsers,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rpc::GetUser => "GetUser",
+            Rpc::GetUserByMail => "GetUserByMail",
+            Rpc::GetAllUsers => "GetAllUsers",
+            Rpc::SearchUsers => "SearchUsers",
+            Rpc::CreateUser => "CreateUser",
+            Rpc::UpdateNameUser => "UpdateNameUser",
+            Rpc::UpdateMailUser => "UpdateMailUser",
+            Rpc::DeleteUser => "DeleteUser",
+            Rpc::RestoreUser => "RestoreUser",
+            Rpc::PurgeDeletedUsers => "PurgeDeletedUsers",
+            Rpc::ResetUserTable => "ResetUserTable",
+            Rpc::ListAuditEvents => "ListAuditEvents",
+            Rpc::WatchUsers => "WatchUsers",
+        }
+    }
+}
+
+impl fmt::Display for Rpc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Rpc {
+    type Err = ErrorKinsper;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Rpc::ALL
+            .into_iter()
+            .find(|rpc| rpc.as_str() == value)
+            .ok_or_else(|| ErrorKinsper::InvalidArgument(format!("Unknown rpc: {:?}", value)))
+    }
+}
+
+// Roles por defecto: reader solo lee usuarios, operator ademas los modifica y lee
+// la auditoria, admin puede todo incluido purgar y vaciar la tabla
+const READER_RPCS: [Rpc; 5] = [
+    Rpc::GetUser,
+    Rpc::GetUserByMail,
+    Rpc::GetAllUsers,
+    Rpc::SearchUsers,
+    Rpc::WatchUsers,
+];
+const OPERATOR_RPCS: [Rpc; 6] = [
+    Rpc::CreateUser,
+    Rpc::UpdateNameUser,
+    Rpc::UpdateMailUser,
+    Rpc::DeleteUser,
+    Rpc::RestoreUser,
+    Rpc::ListAuditEvents,
+];
+// En el archivo de roles equivale a todos los RPCs
+const ALL_RPCS: &str = "*";
+
+// Archivo de roles en TOML:
+//
+// [roles]
+// reader = ["GetUser", "GetAllUsers"]
+// admin = ["*"]
+//
+// [subjects]
+// ci-bot = ["reader"]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PolicyFile {
+    roles: Option<HashMap<String, Vec<String>>>,
+    subjects: HashMap<String, Vec<String>>,
+}
+
+// Que RPCs puede llamar cada rol y que roles tiene cada identidad, ademas de los que
+// traiga su token
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorizationPolicy {
+    roles: HashMap<String, HashSet<Rpc>>,
+    subjects: HashMap<String, Vec<String>>,
+}
+
+impl Default for AuthorizationPolicy {
+    fn default() -> Self {
+        let reader: HashSet<Rpc> = READER_RPCS.into_iter().collect();
+        let operator: HashSet<Rpc> = reader.iter().copied().chain(OPERATOR_RPCS).collect();
+        AuthorizationPolicy {
+            roles: HashMap::from([
+                ("reader".to_string(), reader),
+                ("operator".to_string(), operator),
+                ("admin".to_string(), Rpc::ALL.into_iter().collect()),
+            ]),
+            subjects: HashMap::new(),
+        }
+    }
+}
+
+impl AuthorizationPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ErrorKinsper> {
+        let content = std::fs::read_to_string(path).map_err(|err| {
+            ErrorKinsper::InvalidArgument(format!("Couldn't read roles file {:?}: {}", path, err))
+        })?;
+        Self::from_toml(&content).map_err(|err| match err {
+            ErrorKinsper::InvalidArgument(msg) => {
+                ErrorKinsper::InvalidArgument(format!("{:?}: {}", path, msg))
+            }
+            err => err,
+        })
+    }
+
+    // Sin seccion [roles] se usan los roles por defecto
+    pub fn from_toml(content: &str) -> Result<Self, ErrorKinsper> {
+        let file: PolicyFile = toml::from_str(content)
+            .map_err(|err| ErrorKinsper::InvalidArgument(format!("Invalid roles file: {}", err)))?;
+
+        let mut policy = AuthorizationPolicy::default();
+        if let Some(roles) = file.roles {
+            policy.roles = roles
+                .into_iter()
+                .map(|(role, rpcs)| Ok((role, parse_rpcs(&rpcs)?)))
+                .collect::<Result<_, ErrorKinsper>>()?;
+        }
+        for (subject, roles) in file.subjects {
+            policy = policy.with_subject_roles(&subject, &roles)?;
+        }
+        Ok(policy)
+    }
+
+    pub fn with_subject_roles(
+        mut self,
+        subject: &str,
+        roles: &[String],
+    ) -> Result<Self, ErrorKinsper> {
+        if let Some(role) = roles.iter().find(|role| !self.roles.contains_key(*role)) {
+            return Err(ErrorKinsper::InvalidArgument(format!(
+                "Unknown role {:?} for subject {:?}",
+                role, subject
+            )));
+        }
+        self.subjects.insert(subject.to_string(), roles.to_vec());
+        Ok(self)
+    }
+
+    // Una API key sin roles propios ni asignados por subject no podria llamar a ningun
+    // RPC, se rechaza al arrancar en lugar de negar cada pedido
+    pub fn check_api_key(&self, api_key: &ApiKey) -> Result<(), ErrorKinsper> {
+        if let Some(role) = api_key
+            .roles
+            .iter()
+            .find(|role| !self.roles.contains_key(*role))
+        {
+            return Err(ErrorKinsper::InvalidArgument(format!(
+                "Unknown role {:?} for api key {:?}",
+                role, api_key.name
+            )));
+        }
+        if api_key.roles.is_empty() && !self.subjects.contains_key(&api_key.name) {
+            return Err(ErrorKinsper::InvalidArgument(format!(
+                "Api key {:?} has no roles, use <name>:<key>:<role> or bind it in the roles file",
+                api_key.name
+            )));
+        }
+        Ok(())
+    }
+
+    // Sin identidad el servidor no exige autenticacion y no hay nada que controlar
+    pub fn authorize(&self, identity: Option<&AuthIdentity>, rpc: Rpc) -> Result<(), ErrorKinsper> {
+        let Some(identity) = identity else {
+            return Ok(());
+        };
+        let subject_roles = self.subjects.get(&identity.subject).into_iter().flatten();
+        let allowed = identity
+            .roles
+            .iter()
+            .chain(subject_roles)
+            .filter_map(|role| self.roles.get(role))
+            .any(|rpcs| rpcs.contains(&rpc));
+        if allowed {
+            Ok(())
+        } else {
+            Err(ErrorKinsper::PermissionDenied(format!(
+                "{} is not allowed to call {}",
+                identity.subject, rpc
+            )))
+        }
+    }
+}
+
+fn parse_rpcs(rpcs: &[String]) -> Result<HashSet<Rpc>, ErrorKinsper> {
+    let mut parsed = HashSet::new();
+    for rpc in rpcs {
+        if rpc == ALL_RPCS {
+            parsed.extend(Rpc::ALL);
+        } else {
+            parsed.insert(rpc.parse()?);
+        }
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod authorization_tests {
+    use crate::auth::{AuthIdentity, AuthMethod};
+    use crate::authorization::{AuthorizationPolicy, Rpc};
+    use crate::errors::ErrorKinsper;
+
+    fn identity(subject: &str, roles: &[&str]) -> AuthIdentity {
+        AuthIdentity {
+            subject: subject.to_string(),
+            method: AuthMethod::Jwt,
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        }
+    }
+
+    fn allowed(policy: &AuthorizationPolicy, identity: &AuthIdentity, rpc: Rpc) -> bool {
+        match policy.authorize(Some(identity), rpc) {
+            Ok(()) => true,
+            Err(ErrorKinsper::PermissionDenied(_)) => false,
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test01_when_default_policy_then_each_role_calls_only_its_rpcs() {
+        use Rpc::*;
+        // reader, operator, admin y sin roles
+        let expected = [
+            (GetUser, [true, true, true, false]),
+            (GetUserByMail, [true, true, true, false]),
+            (GetAllUsers, [true, true, true, false]),
+            (SearchUsers, [true, true, true, false]),
+            (WatchUsers, [true, true, true, false]),
+            (CreateUser, [false, true, true, false]),
+            (UpdateNameUser, [false, true, true, false]),
+            (UpdateMailUser, [false, true, true, false]),
+            (DeleteUser, [false, true, true, false]),
+            (RestoreUser, [false, true, true, false]),
+            (ListAuditEvents, [false, true, true, false]),
+            (PurgeDeletedUsers, [false, false, true, false]),
+            (ResetUserTable, [false, false, true, false]),
+        ];
+        assert_eq!(expected.len(), Rpc::ALL.len());
+
+        let policy = AuthorizationPolicy::new();
+        let identities = [
+            identity("reader", &["reader"]),
+            identity("operator", &["operator"]),
+            identity("admin", &["admin"]),
+            identity("nobody", &[]),
+        ];
+        for (rpc, expected) in expected {
+            for (identity, expected) in identities.iter().zip(expected) {
+                assert_eq!(
+                    allowed(&policy, identity, rpc),
+                    expected,
+                    "{} calling {}",
+                    identity.subject,
+                    rpc
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test02_when_no_identity_then_authentication_is_disabled_and_every_rpc_is_allowed() {
+        let policy = AuthorizationPolicy::new();
+
+        for rpc in Rpc::ALL {
+            assert!(policy.authorize(None, rpc).is_ok());
+        }
+    }
+
+    #[test]
+    fn test03_when_roles_file_then_replaces_roles_and_binds_subjects() {
+        let policy = AuthorizationPolicy::from_toml(
+            r#"
+            [roles]
+            auditor = ["ListAuditEvents"]
+            root = ["*"]
+
+            [subjects]
+            ci-bot = ["auditor"]
+            "#,
+        )
+        .unwrap();
+
+        let ci_bot = identity("ci-bot", &[]);
+        assert!(allowed(&policy, &ci_bot, Rpc::ListAuditEvents));
+        assert!(!allowed(&policy, &ci_bot, Rpc::GetUser));
+        assert!(allowed(
+            &policy,
+            &identity("alice", &["root"]),
+            Rpc::ResetUserTable
+        ));
+        // Los roles por defecto ya no existen
+        assert!(!allowed(
+            &policy,
+            &identity("bob", &["admin"]),
+            Rpc::GetUser
+        ));
+    }
+
+    #[test]
+    fn test04_when_roles_file_is_invalid_then_returns_error() {
+        for toml in [
+            "[roles]\nreader = [\"GetUsers\"]",
+            "[subjects]\nci-bot = [\"auditor\"]",
+            "unknown = 1",
+        ] {
+            assert!(AuthorizationPolicy::from_toml(toml).is_err(), "{}", toml);
+        }
+        assert_eq!(
+            "ResetUserTable".parse::<Rpc>().unwrap(),
+            Rpc::ResetUserTable
+        );
+    }
+
+    #[test]
+    fn test05_when_api_key_has_no_known_roles_then_check_fails() {
+        let policy = AuthorizationPolicy::new()
+            .with_subject_roles("ci-bot", &["reader".to_string()])
+            .unwrap();
+
+        for api_key in [
+            "admin:s3cret:admin",
+            "viewer:key:reader+operator",
+            "ci-bot:key",
+        ] {
+            assert!(
+                policy.check_api_key(&api_key.parse().unwrap()).is_ok(),
+                "{}",
+                api_key
+            );
+        }
+        for api_key in ["admin:s3cret", "admin:s3cret:root"] {
+            assert!(
+                policy.check_api_key(&api_key.parse().unwrap()).is_err(),
+                "{}",
+                api_key
+            );
+        }
+    }
+}
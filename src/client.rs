@@ -1,10 +1,20 @@
-use clap::Parser;
-use kinsper_rust_test::{errors::ErrorKinsper, SERVER_LOCALHOST, SERVER_LOCALPORT};
+use std::io::Write;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{ArgGroup, Parser};
+use kinsper_rust_test::connection::{ConnectionOptions, MetadataInterceptor};
+use kinsper_rust_test::errors::{ErrorDetails, ErrorKinsper};
+use kinsper_rust_test::handler_server::{from_timestamp, to_timestamp};
+use prost_types::Timestamp;
+use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Channel;
+use tonic::{Status, Streaming};
 use user_service::{
-    user_service_client::UserServiceClient, CreateUserRequest, DeleteUserRequest,
-    GetAllUserRequest, GetUserRequest, ResetUserTableRequest, UpdateUserMailRequest,
-    UpdateUserNameRequest,
+    user_service_client::UserServiceClient, AuditOperation, CreateUserRequest, DeleteUserRequest,
+    GetAllUserRequest, GetUserByMailRequest, GetUserRequest, GetUserResponse,
+    ListAuditEventsRequest, PurgeDeletedUsersRequest, ResetUserTableRequest, RestoreUserRequest,
+    SearchUsersRequest, UpdateUserMailRequest, UpdateUserNameRequest, UserSortField,
+    WatchUsersRequest,
 };
 
 use kinsper_rust_test::QUERY_LIMIT_CLIENT;
@@ -16,31 +26,128 @@ pub mod user_service {
 
 #[derive(Debug, Parser)]
 struct Options {
+    // Identidad que queda registrada en la auditoria del servidor
+    #[clap(long, global = true)]
+    caller: Option<String>,
+    #[clap(flatten)]
+    connection: ConnectionOptions,
     #[clap(subcommand)]
     command: Command,
 }
 
+type Client = UserServiceClient<InterceptedService<Channel, MetadataInterceptor>>;
+
+fn format_timestamp(timestamp: &Option<Timestamp>) -> String {
+    match timestamp.as_ref().map(from_timestamp) {
+        Some(Ok(at)) => at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        _ => "-".to_string(),
+    }
+}
+
+// Codigo y mensaje del status mas los detalles de google.rpc que haya mandado el
+// servidor: la reason estable, cuando reintentar y los campos invalidos
+fn format_status(status: &Status) -> String {
+    let details = ErrorDetails::from_status(status);
+    let mut formatted = format!("{:?}: {}", status.code(), status.message());
+    if let Some(info) = &details.error_info {
+        formatted.push_str(&format!(" | REASON: {} ({})", info.reason, info.domain));
+    }
+    if let Some(retry_delay) = details.retry_delay() {
+        formatted.push_str(&format!(" | RETRY AFTER: {:?}", retry_delay));
+    }
+    for violation in details
+        .bad_request
+        .iter()
+        .flat_map(|bad_request| &bad_request.field_violations)
+    {
+        formatted.push_str(&format!(
+            "\n  - {}: {}",
+            violation.field, violation.description
+        ));
+    }
+    formatted
+}
+
 #[derive(Debug, Parser)]
 enum Command {
     Get(GetOptions),
     GetAll(GetAllOptions),
+    Search(SearchOptions),
     Create(CreateOptions),
     Delete(DeleteOptions),
+    Restore(RestoreOptions),
+    Purge(PurgeOptions),
     UpdateName(UpdateNameOptions),
     UpdateMail(UpdateMailOptions),
-    ResetTable,
+    ResetTable(ResetTableOptions),
+    Audit(AuditOptions),
+    Watch(WatchOptions),
+}
+
+#[derive(Debug, Parser)]
+struct ResetTableOptions {
+    // Nombre de la base del servidor, que el servidor pide como confirmacion
+    #[clap(long)]
+    database: Option<String>,
+    // Copia los usuarios a una tabla nueva antes de vaciar la tabla
+    #[clap(long)]
+    snapshot: bool,
+    // No pide confirmacion por consola
+    #[clap(long, requires = "database")]
+    yes: bool,
+}
+
+fn prompt(message: &str) -> Result<String, ErrorKinsper> {
+    print!("{}", message);
+    std::io::stdout()
+        .flush()
+        .map_err(|err| ErrorKinsper::InternalServer(err.to_string()))?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(|err| ErrorKinsper::InternalServer(err.to_string()))?;
+    Ok(answer.trim().to_string())
+}
+
+// Devuelve el nombre de la base confirmado, None si se cancelo
+fn confirm_reset(opts: &ResetTableOptions) -> Result<Option<String>, ErrorKinsper> {
+    match &opts.database {
+        Some(database) if opts.yes => Ok(Some(database.clone())),
+        Some(database) => {
+            let answer = prompt(&format!(
+                "This deletes every user of {}. Continue? [y/N] ",
+                database
+            ))?;
+            Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes").then(|| database.clone()))
+        }
+        None => {
+            let database = prompt("This deletes every user. Type the database name to confirm: ")?;
+            Ok((!database.is_empty()).then_some(database))
+        }
+    }
 }
 
-async fn reset_table(mut client: UserServiceClient<Channel>) -> Result<(), ErrorKinsper> {
-    let request = tonic::Request::new(ResetUserTableRequest {});
+async fn reset_table(opts: ResetTableOptions, mut client: Client) -> Result<(), ErrorKinsper> {
+    let Some(confirmation) = confirm_reset(&opts)? else {
+        println!("User table reset cancelled");
+        return Ok(());
+    };
+    let request = tonic::Request::new(ResetUserTableRequest {
+        confirmation,
+        snapshot: opts.snapshot,
+    });
 
     let response = client.reset_user_table(request).await;
     match response {
-        Ok(_) => {
+        Ok(response) => {
             println!("User table reset successfully");
+            let snapshot_table = response.into_inner().snapshot_table;
+            if !snapshot_table.is_empty() {
+                println!("Users copied to table {}", snapshot_table);
+            }
         }
         Err(e) => {
-            eprint!("USER TABLE NOT RESET. ERROR: {:?}", e);
+            eprint!("USER TABLE NOT RESET. ERROR: {}", format_status(&e));
         }
     }
     Ok(())
@@ -51,15 +158,16 @@ struct UpdateNameOptions {
     id: String,
     #[clap(long)]
     name: String,
+    // 0 no controla la version actual del usuario
+    #[clap(default_value = "0", long)]
+    expected_version: u64,
 }
 
-async fn update_name(
-    opts: UpdateNameOptions,
-    mut client: UserServiceClient<Channel>,
-) -> Result<(), ErrorKinsper> {
+async fn update_name(opts: UpdateNameOptions, mut client: Client) -> Result<(), ErrorKinsper> {
     let request = tonic::Request::new(UpdateUserNameRequest {
         id: Some(user_service::UserId { id: opts.id }),
         name: opts.name,
+        expected_version: opts.expected_version,
     });
 
     let response = client.update_name_user(request).await;
@@ -68,7 +176,7 @@ async fn update_name(
             println!("User name updated successfully");
         }
         Err(e) => {
-            eprint!("USER NAME NOT UPDATED. ERROR: {:?}", e);
+            eprint!("USER NAME NOT UPDATED. ERROR: {}", format_status(&e));
         }
     }
     Ok(())
@@ -80,15 +188,16 @@ struct UpdateMailOptions {
     id: String,
     #[clap(long)]
     mail: String,
+    // 0 no controla la version actual del usuario
+    #[clap(default_value = "0", long)]
+    expected_version: u64,
 }
 
-async fn update_mail(
-    opts: UpdateMailOptions,
-    mut client: UserServiceClient<Channel>,
-) -> Result<(), ErrorKinsper> {
+async fn update_mail(opts: UpdateMailOptions, mut client: Client) -> Result<(), ErrorKinsper> {
     let request = tonic::Request::new(UpdateUserMailRequest {
         id: Some(user_service::UserId { id: opts.id }),
         mail: opts.mail,
+        expected_version: opts.expected_version,
     });
 
     let response = client.update_mail_user(request).await;
@@ -97,7 +206,7 @@ async fn update_mail(
             println!("User mail updated successfully");
         }
         Err(e) => {
-            eprint!("USER MAIL NOT UPDATED. ERROR: {:?}", e);
+            eprint!("USER MAIL NOT UPDATED. ERROR: {}", format_status(&e));
         }
     }
     Ok(())
@@ -107,14 +216,15 @@ async fn update_mail(
 struct DeleteOptions {
     #[clap(long)]
     id: String,
+    // 0 no controla la version actual del usuario
+    #[clap(default_value = "0", long)]
+    expected_version: u64,
 }
 
-async fn delete(
-    opts: DeleteOptions,
-    mut client: UserServiceClient<Channel>,
-) -> Result<(), ErrorKinsper> {
+async fn delete(opts: DeleteOptions, mut client: Client) -> Result<(), ErrorKinsper> {
     let request = tonic::Request::new(DeleteUserRequest {
         id: Some(user_service::UserId { id: opts.id }),
+        expected_version: opts.expected_version,
     });
 
     let response = client.delete_user(request).await;
@@ -123,39 +233,91 @@ async fn delete(
             println!("User deleted successfully");
         }
         Err(e) => {
-            eprint!("USER NOT DELETED. ERROR: {:?}", e);
+            eprint!("USER NOT DELETED. ERROR: {}", format_status(&e));
         }
     }
     Ok(())
 }
 
 #[derive(Debug, Parser)]
-struct CreateOptions {
+struct RestoreOptions {
     #[clap(long)]
     id: String,
+}
+
+async fn restore(opts: RestoreOptions, mut client: Client) -> Result<(), ErrorKinsper> {
+    let request = tonic::Request::new(RestoreUserRequest {
+        id: Some(user_service::UserId { id: opts.id }),
+    });
+
+    let response = client.restore_user(request).await;
+    match response {
+        Ok(_) => {
+            println!("User restored successfully");
+        }
+        Err(e) => {
+            eprint!("USER NOT RESTORED. ERROR: {}", format_status(&e));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Parser)]
+struct PurgeOptions {
+    // 0 usa la retencion por defecto del servidor
+    #[clap(default_value = "0", long)]
+    retention_days: u32,
+}
+
+async fn purge(opts: PurgeOptions, mut client: Client) -> Result<(), ErrorKinsper> {
+    let request = tonic::Request::new(PurgeDeletedUsersRequest {
+        retention_days: opts.retention_days,
+    });
+
+    let response = client.purge_deleted_users(request).await;
+    match response {
+        Ok(response) => {
+            println!("Deleted users purged: {}", response.into_inner().purged);
+        }
+        Err(e) => {
+            eprint!("DELETED USERS NOT PURGED. ERROR: {}", format_status(&e));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Parser)]
+struct CreateOptions {
+    // Si no se indica, el servidor genera el id
+    #[clap(long)]
+    id: Option<String>,
     #[clap(long)]
     name: String,
     #[clap(long)]
     mail: String,
 }
 
-async fn create(
-    opts: CreateOptions,
-    mut client: UserServiceClient<Channel>,
-) -> Result<(), ErrorKinsper> {
+async fn create(opts: CreateOptions, mut client: Client) -> Result<(), ErrorKinsper> {
     let request = tonic::Request::new(CreateUserRequest {
-        id: Some(user_service::UserId { id: opts.id }),
+        id: opts.id.map(|id| user_service::UserId { id }),
         name: opts.name,
         mail: opts.mail,
     });
 
     let response = client.create_user(request).await;
     match response {
-        Ok(_) => {
-            println!("User created successfully");
+        Ok(response) => {
+            let user = response.into_inner().user.unwrap_or_default();
+            println!(
+                "User created successfully - ID: {} | NAME: {} | MAIL: {} | CREATED: {}",
+                user.id.unwrap_or_default().id,
+                user.name,
+                user.mail,
+                format_timestamp(&user.created_at)
+            );
         }
         Err(e) => {
-            eprint!("USER NOT CREATED. ERROR: {:?}", e);
+            eprint!("USER NOT CREATED. ERROR: {}", format_status(&e));
         }
     }
     Ok(())
@@ -165,35 +327,44 @@ async fn create(
 struct GetAllOptions {
     #[clap(default_value = QUERY_LIMIT_CLIENT, long)]
     limit: u32,
+    #[clap(default_value = "0", long)]
+    page_size: u32,
+    #[clap(default_value = "", long)]
+    page_token: String,
+    #[clap(long)]
+    include_deleted: bool,
+    // Rangos en RFC 3339 (ej: 2024-01-31T00:00:00Z), inicio inclusivo y fin exclusivo
+    #[clap(long)]
+    created_after: Option<DateTime<Utc>>,
+    #[clap(long)]
+    created_before: Option<DateTime<Utc>>,
+    #[clap(long)]
+    updated_after: Option<DateTime<Utc>>,
+    #[clap(long)]
+    updated_before: Option<DateTime<Utc>>,
 }
 
-async fn get_all(
-    opts: GetAllOptions,
-    mut client: UserServiceClient<Channel>,
-) -> Result<(), ErrorKinsper> {
-    let request = tonic::Request::new(GetAllUserRequest { limit: opts.limit });
+async fn get_all(opts: GetAllOptions, mut client: Client) -> Result<(), ErrorKinsper> {
+    let request = tonic::Request::new(GetAllUserRequest {
+        limit: opts.limit,
+        page_size: opts.page_size,
+        page_token: opts.page_token,
+        include_deleted: opts.include_deleted,
+        created_after: opts.created_after.map(to_timestamp),
+        created_before: opts.created_before.map(to_timestamp),
+        updated_after: opts.updated_after.map(to_timestamp),
+        updated_before: opts.updated_before.map(to_timestamp),
+    });
 
     match client.get_all_users(request).await {
         Ok(response) => {
-            let mut stream = response.into_inner();
-            while let Ok(user) = stream.message().await {
-                if let Some(user) = user {
-                    println!(
-                        "User obtained - ID: {} | NAME: {} | MAIL: {}",
-                        user.id.unwrap().id,
-                        user.name,
-                        user.mail
-                    );
-                } else {
-                    break;
-                }
-            }
+            print_users(response.into_inner()).await;
         }
         Err(e) => {
             if e.code() == tonic::Code::NotFound {
                 println!("No users found in the database");
             } else {
-                eprint!("ERROR: {:?}", e);
+                eprint!("ERROR: {}", format_status(&e));
             }
         }
     }
@@ -202,29 +373,263 @@ async fn get_all(
 }
 
 #[derive(Debug, Parser)]
-struct GetOptions {
+struct WatchOptions {
+    // Resume token del ultimo evento recibido, para no perder cambios al reconectar
+    #[clap(default_value = "", long)]
+    resume_token: String,
     #[clap(long)]
-    id: String,
+    user_id: Option<String>,
 }
 
-async fn get(opts: GetOptions, mut client: UserServiceClient<Channel>) -> Result<(), ErrorKinsper> {
-    let request = tonic::Request::new(GetUserRequest {
-        id: Some(user_service::UserId { id: opts.id }),
+async fn watch(opts: WatchOptions, mut client: Client) -> Result<(), ErrorKinsper> {
+    let request = tonic::Request::new(WatchUsersRequest {
+        resume_token: opts.resume_token,
+        user_id: opts.user_id.unwrap_or_default(),
+    });
+
+    match client.watch_users(request).await {
+        Ok(response) => {
+            let mut stream = response.into_inner();
+            loop {
+                match stream.message().await {
+                    Ok(Some(event)) => {
+                        let user = match &event.user {
+                            Some(user) => format!(
+                                " | NAME: {} | MAIL: {} | VERSION: {}",
+                                user.name, user.mail, user.version
+                            ),
+                            None => String::new(),
+                        };
+                        println!(
+                            "{} - {:?} | USER: {}{} | CALLER: {} | RESUME TOKEN: {}",
+                            format_timestamp(&event.changed_at),
+                            event.operation(),
+                            event.user_id,
+                            user,
+                            event.caller,
+                            event.resume_token
+                        );
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprint!("WATCH INTERRUPTED. ERROR: {}", format_status(&e));
+                        break;
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            eprint!("WATCH NOT STARTED. ERROR: {}", format_status(&e));
+        }
+    }
+    Ok(())
+}
+
+// Imprime los usuarios de GetAllUsers o SearchUsers, junto con el token de la
+// pagina siguiente si lo hay. Devuelve la cantidad de usuarios recibidos
+async fn print_users(mut stream: Streaming<GetUserResponse>) -> usize {
+    let mut received = 0;
+    while let Ok(Some(user)) = stream.message().await {
+        received += 1;
+        println!(
+            "User obtained - ID: {} | NAME: {} | MAIL: {} | CREATED: {} | UPDATED: {} | VERSION: {}{}",
+            user.id.unwrap_or_default().id,
+            user.name,
+            user.mail,
+            format_timestamp(&user.created_at),
+            format_timestamp(&user.updated_at),
+            user.version,
+            if user.deleted { " | DELETED" } else { "" }
+        );
+        if user.score != 0.0 {
+            println!("Score: {:.4}", user.score);
+        }
+        if !user.next_page_token.is_empty() {
+            println!("Next page token: {}", user.next_page_token);
+        }
+    }
+    received
+}
+
+#[derive(Debug, Parser)]
+struct SearchOptions {
+    // Busqueda de texto completo sobre el nombre, ordena por relevancia
+    #[clap(long)]
+    query: Option<String>,
+    // Sin distinguir mayusculas
+    #[clap(long)]
+    name_contains: Option<String>,
+    #[clap(long)]
+    name_prefix: Option<String>,
+    #[clap(long)]
+    mail: Option<String>,
+    // Dominio del mail, lo que va despues del @
+    #[clap(long)]
+    mail_domain: Option<String>,
+    // id, name o mail
+    #[clap(default_value = "id", long)]
+    sort_by: String,
+    #[clap(long)]
+    descending: bool,
+    #[clap(default_value = "0", long)]
+    page_size: u32,
+    #[clap(default_value = "", long)]
+    page_token: String,
+    #[clap(long)]
+    include_deleted: bool,
+}
+
+async fn search(opts: SearchOptions, mut client: Client) -> Result<(), ErrorKinsper> {
+    let sort_by =
+        UserSortField::from_str_name(&format!("USER_SORT_FIELD_{}", opts.sort_by.to_uppercase()))
+            .ok_or_else(|| {
+            ErrorKinsper::InvalidArgument(format!("Invalid sort field: {}", opts.sort_by))
+        })?;
+
+    let request = tonic::Request::new(SearchUsersRequest {
+        name_contains: opts.name_contains.unwrap_or_default(),
+        name_prefix: opts.name_prefix.unwrap_or_default(),
+        mail: opts.mail.unwrap_or_default(),
+        mail_domain: opts.mail_domain.unwrap_or_default(),
+        sort_by: sort_by.into(),
+        descending: opts.descending,
+        page_size: opts.page_size,
+        page_token: opts.page_token,
+        include_deleted: opts.include_deleted,
+        query: opts.query.unwrap_or_default(),
+    });
+
+    match client.search_users(request).await {
+        Ok(response) => {
+            if print_users(response.into_inner()).await == 0 {
+                println!("No users match the search");
+            }
+        }
+        Err(e) => {
+            eprint!("SEARCH FAILED. ERROR: {}", format_status(&e));
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Parser)]
+struct AuditOptions {
+    #[clap(long)]
+    user_id: Option<String>,
+    // create, update, delete, restore, purge o reset
+    #[clap(long)]
+    operation: Option<String>,
+    // Rango en RFC 3339, inicio inclusivo y fin exclusivo
+    #[clap(long)]
+    created_after: Option<DateTime<Utc>>,
+    #[clap(long)]
+    created_before: Option<DateTime<Utc>>,
+    #[clap(default_value = "0", long)]
+    page_size: u32,
+    #[clap(default_value = "", long)]
+    page_token: String,
+}
+
+async fn audit(opts: AuditOptions, mut client: Client) -> Result<(), ErrorKinsper> {
+    let operation = match opts.operation {
+        Some(operation) => {
+            AuditOperation::from_str_name(&format!("AUDIT_OPERATION_{}", operation.to_uppercase()))
+                .ok_or_else(|| {
+                    ErrorKinsper::InvalidArgument(format!("Invalid audit operation: {}", operation))
+                })?
+        }
+        None => AuditOperation::Unspecified,
+    };
+
+    let request = tonic::Request::new(ListAuditEventsRequest {
+        user_id: opts.user_id.unwrap_or_default(),
+        operation: operation.into(),
+        created_after: opts.created_after.map(to_timestamp),
+        created_before: opts.created_before.map(to_timestamp),
+        page_size: opts.page_size,
+        page_token: opts.page_token,
     });
 
-    let response = client.get_user(request).await;
+    match client.list_audit_events(request).await {
+        Ok(response) => {
+            let response = response.into_inner();
+            if response.events.is_empty() {
+                println!("No audit events found");
+            }
+            for event in response.events {
+                println!(
+                    "Audit event {} - {} | {:?} | USER: {} | CALLER: {} | BEFORE: {} | AFTER: {}",
+                    event.id,
+                    format_timestamp(&event.created_at),
+                    event.operation(),
+                    event.user_id,
+                    event.caller,
+                    if event.before.is_empty() {
+                        "-"
+                    } else {
+                        &event.before
+                    },
+                    if event.after.is_empty() {
+                        "-"
+                    } else {
+                        &event.after
+                    },
+                );
+            }
+            if !response.next_page_token.is_empty() {
+                println!("Next page token: {}", response.next_page_token);
+            }
+        }
+        Err(e) => {
+            eprint!("AUDIT EVENTS NOT LISTED. ERROR: {}", format_status(&e));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Parser)]
+#[clap(group(ArgGroup::new("key").required(true).args(["id", "mail"])))]
+struct GetOptions {
+    #[clap(long)]
+    id: Option<String>,
+    #[clap(long)]
+    mail: Option<String>,
+}
+
+async fn get(opts: GetOptions, mut client: Client) -> Result<(), ErrorKinsper> {
+    let response = match (opts.id, opts.mail) {
+        (_, Some(mail)) => {
+            client
+                .get_user_by_mail(tonic::Request::new(GetUserByMailRequest { mail }))
+                .await
+        }
+        (id, None) => {
+            client
+                .get_user(tonic::Request::new(GetUserRequest {
+                    id: Some(user_service::UserId {
+                        id: id.unwrap_or_default(),
+                    }),
+                }))
+                .await
+        }
+    };
+
     match response {
         Ok(response) => {
             let response = response.into_inner();
             println!(
-                "User obtained - ID: {} | NAME: {} | MAIL: {}",
+                "User obtained - ID: {} | NAME: {} | MAIL: {} | CREATED: {} | UPDATED: {} | VERSION: {}",
                 response.id.unwrap().id,
                 response.name,
-                response.mail
+                response.mail,
+                format_timestamp(&response.created_at),
+                format_timestamp(&response.updated_at),
+                response.version
             );
         }
         Err(e) => {
-            eprint!("USER NOT FOUND. ERROR: {:?}", e);
+            eprint!("USER NOT FOUND. ERROR: {}", format_status(&e));
         }
     }
     Ok(())
@@ -234,10 +639,14 @@ async fn get(opts: GetOptions, mut client: UserServiceClient<Channel>) -> Result
 async fn main() -> Result<(), ErrorKinsper> {
     let opts = Options::parse();
 
-    let addr = format!("http://{}:{}", SERVER_LOCALHOST, SERVER_LOCALPORT);
-    let client = UserServiceClient::connect(addr)
+    let channel = opts
+        .connection
+        .endpoint()?
+        .connect()
         .await
         .map_err(|_| ErrorKinsper::InternalServer("Error connecting to server".to_string()))?;
+    let interceptor = opts.connection.interceptor(opts.caller.as_deref())?;
+    let client = UserServiceClient::with_interceptor(channel, interceptor);
 
     use Command::*;
     match opts.command {
@@ -245,9 +654,14 @@ async fn main() -> Result<(), ErrorKinsper> {
         GetAll(opts) => get_all(opts, client).await?,
         Create(opts) => create(opts, client).await?,
         Delete(opts) => delete(opts, client).await?,
+        Restore(opts) => restore(opts, client).await?,
+        Purge(opts) => purge(opts, client).await?,
         UpdateName(opts) => update_name(opts, client).await?,
         UpdateMail(opts) => update_mail(opts, client).await?,
-        ResetTable => reset_table(client).await?,
+        ResetTable(opts) => reset_table(opts, client).await?,
+        Audit(opts) => audit(opts, client).await?,
+        Search(opts) => search(opts, client).await?,
+        Watch(opts) => watch(opts, client).await?,
     };
 
     Ok(())
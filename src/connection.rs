@@ -0,0 +1,131 @@
+use clap::Args;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::transport::Endpoint;
+use tonic::{Request, Status};
+
+use crate::auth::AUTHORIZATION_METADATA_KEY;
+use crate::errors::ErrorKinsper;
+use crate::tls::ClientTlsOptions;
+use crate::{CALLER_METADATA_KEY, SERVER_LOCALHOST, SERVER_LOCALPORT};
+
+// Opciones de conexion al servidor compartidas por client y multi-clients
+#[derive(Debug, Clone, Args)]
+pub struct ConnectionOptions {
+    // Direccion y puerto del servidor, los mismos que --host y --port del server
+    #[arg(long, env = "CLIENT_HOST", default_value = SERVER_LOCALHOST, global = true)]
+    pub host: String,
+    #[arg(long, env = "CLIENT_PORT", default_value_t = SERVER_LOCALPORT, global = true)]
+    pub port: u16,
+    // API key o JWT, se envia como authorization: Bearer <token>
+    #[arg(long, env = "CLIENT_TOKEN", global = true)]
+    pub token: Option<String>,
+    #[command(flatten)]
+    pub tls: ClientTlsOptions,
+}
+
+impl ConnectionOptions {
+    pub fn endpoint(&self) -> Result<Endpoint, ErrorKinsper> {
+        self.tls.endpoint(&self.host, self.port)
+    }
+
+    // caller es la identidad para la auditoria, solo la usa el servidor si no exige
+    // autenticacion
+    pub fn interceptor(&self, caller: Option<&str>) -> Result<MetadataInterceptor, ErrorKinsper> {
+        let caller = caller
+            .map(|caller| caller.parse())
+            .transpose()
+            .map_err(|_| ErrorKinsper::InvalidArgument("Invalid caller".to_string()))?;
+        let authorization = self
+            .token
+            .as_ref()
+            .map(|token| format!("Bearer {}", token).parse())
+            .transpose()
+            .map_err(|_| ErrorKinsper::InvalidArgument("Invalid token".to_string()))?;
+        Ok(MetadataInterceptor {
+            caller,
+            authorization,
+        })
+    }
+}
+
+// Agrega la metadata comun a todos los pedidos
+#[derive(Debug, Clone)]
+pub struct MetadataInterceptor {
+    caller: Option<MetadataValue<Ascii>>,
+    authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl Interceptor for MetadataInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(caller) = &self.caller {
+            request
+                .metadata_mut()
+                .insert(CALLER_METADATA_KEY, caller.clone());
+        }
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert(AUTHORIZATION_METADATA_KEY, authorization.clone());
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod connection_tests {
+    use clap::Parser;
+    use tonic::service::Interceptor;
+    use tonic::Request;
+
+    use crate::auth::AUTHORIZATION_METADATA_KEY;
+    use crate::connection::ConnectionOptions;
+    use crate::CALLER_METADATA_KEY;
+
+    #[derive(Debug, Parser)]
+    struct Options {
+        #[command(flatten)]
+        connection: ConnectionOptions,
+    }
+
+    #[test]
+    fn test01_when_host_and_port_given_then_endpoint_uses_them() {
+        let default = Options::try_parse_from(["client"]).unwrap().connection;
+        let custom = Options::try_parse_from(["client", "--host", "10.0.0.2", "--port", "50061"])
+            .unwrap()
+            .connection;
+
+        assert_eq!(
+            default.endpoint().unwrap().uri().to_string(),
+            "http://127.0.0.1:50051/"
+        );
+        assert_eq!(
+            custom.endpoint().unwrap().uri().to_string(),
+            "http://10.0.0.2:50061/"
+        );
+        assert!(Options::try_parse_from(["client", "--port", "port"]).is_err());
+    }
+
+    #[test]
+    fn test02_when_token_given_then_interceptor_sends_it_as_bearer() {
+        let connection = Options::try_parse_from(["client", "--token", "s3cret"])
+            .unwrap()
+            .connection;
+
+        let request = connection
+            .interceptor(Some("alice"))
+            .unwrap()
+            .call(Request::new(()))
+            .unwrap();
+
+        assert_eq!(
+            request.metadata().get(AUTHORIZATION_METADATA_KEY).unwrap(),
+            "Bearer s3cret"
+        );
+        assert_eq!(
+            request.metadata().get(CALLER_METADATA_KEY).unwrap(),
+            "alice"
+        );
+        assert!(connection.interceptor(Some("bad\ncaller")).is_err());
+    }
+}
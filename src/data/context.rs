@@ -1,19 +1,30 @@
 use std::sync::Arc;
 
-use sqlx::MySqlPool;
+use sqlx::mysql::MySqlPoolOptions;
+use sqlx::MySql;
 
 use crate::errors::ErrorKinsper;
 
-pub struct Database {
-    // https://docs.rs/sqlx/latest/sqlx/struct.Pool.html#why-use-a-pool
-    pub pool: Arc<MySqlPool>,
-}
+use super::{sql::SqlDatabase, DEFAULT_POOL_SIZE};
+
+pub type Database = SqlDatabase<MySql>;
 
 impl Database {
     pub async fn connect(sql_url: &str) -> Result<Database, ErrorKinsper> {
-        let connection = MySqlPool::connect(sql_url).await.map_err(|err| {
-            ErrorKinsper::ConnectionError(format!("Couldn't connect to the database: {}", err))
-        })?;
+        Self::connect_with_pool_size(sql_url, DEFAULT_POOL_SIZE).await
+    }
+
+    pub async fn connect_with_pool_size(
+        sql_url: &str,
+        pool_size: u32,
+    ) -> Result<Database, ErrorKinsper> {
+        let connection = MySqlPoolOptions::new()
+            .max_connections(pool_size)
+            .connect(sql_url)
+            .await
+            .map_err(|err| {
+                ErrorKinsper::ConnectionError(format!("Couldn't connect to the database: {}", err))
+            })?;
         let pool = Arc::new(connection);
 
         Ok(Database { pool })
@@ -1,161 +1,5 @@
-use crate::{data::QUERY_LIMIT, errors::ErrorKinsper};
-
-use super::{
-    context::Database,
-    model::UserModel,
-    scheme::{CreateUserScheme, UpdateUserSchema},
-};
-
-impl Database {
-    fn debug_thread(&self) {
-        log::debug!(
-            "[CURRENT_THREAD: {:?}] | [THREAD_NAME: {:?}]",
-            std::thread::current().id(),
-            std::thread::current().name().unwrap()
-        );
-    }
-    pub async fn drop_table(&self) -> Result<(), ErrorKinsper> {
-        self.debug_thread();
-
-        sqlx::query("DROP TABLE IF EXISTS users;")
-            .execute(self.pool.clone().as_ref())
-            .await?;
-
-        Ok(())
-    }
-
-    pub async fn create_table(&self) -> Result<(), ErrorKinsper> {
-        self.debug_thread();
-
-        sqlx::query(
-            r#"
-                CREATE TABLE IF NOT EXISTS users (
-                id VARCHAR(48) PRIMARY KEY NOT NULL,
-                name VARCHAR(256) NOT NULL,
-                mail VARCHAR(256) NOT NULL
-                )"#,
-        )
-        .execute(self.pool.clone().as_ref())
-        .await?;
-
-        Ok(())
-    }
-
-    pub async fn reset_table(&self) -> Result<(), ErrorKinsper> {
-        self.debug_thread();
-
-        self.drop_table().await?;
-        self.create_table().await?;
-
-        Ok(())
-    }
-
-    pub async fn add_user(&self, user: &CreateUserScheme) -> Result<u64, ErrorKinsper> {
-        self.debug_thread();
-
-        let result = sqlx::query(
-            r#"
-            INSERT INTO users (`id`, `name`, `mail`)
-            VALUES(?, ?, ?)"#,
-        )
-        .bind(&user.id)
-        .bind(&user.name)
-        .bind(&user.mail)
-        .execute(self.pool.clone().as_ref())
-        .await?;
-
-        match result.rows_affected() {
-            0 => Err(ErrorKinsper::AlreadyExists(
-                "User already exists.".to_string(),
-            )),
-            _ => Ok(result.rows_affected()),
-        }
-    }
-
-    pub async fn get_users(&self, limit: Option<u32>) -> Result<Vec<UserModel>, ErrorKinsper> {
-        self.debug_thread();
-
-        let result = sqlx::query_as::<_, UserModel>(
-            r#"
-                SELECT * 
-                FROM users 
-                LIMIT ?"#,
-        )
-        .bind(limit.unwrap_or(QUERY_LIMIT))
-        .fetch_all(self.pool.clone().as_ref())
-        .await?;
-
-        if result.is_empty() {
-            Err(ErrorKinsper::NotFound("No users found.".to_string()))
-        } else {
-            Ok(result)
-        }
-    }
-
-    pub async fn get_user_by_id(&self, id: &str) -> Result<UserModel, ErrorKinsper> {
-        self.debug_thread();
-
-        let result = sqlx::query_as::<_, UserModel>(
-            r#"
-                SELECT * 
-                FROM users 
-                WHERE id = ?"#,
-        )
-        .bind(id)
-        .fetch_one(self.pool.clone().as_ref())
-        .await?;
-
-        Ok(result)
-    }
-
-    pub async fn update_user(
-        &self,
-        id: &str,
-        user: &UpdateUserSchema,
-    ) -> Result<u64, ErrorKinsper> {
-        self.debug_thread();
-
-        let result = sqlx::query(
-            format!(
-                r#"
-                UPDATE users 
-                SET {} 
-                WHERE id = ?"#,
-                user.query_set()
-            )
-            .as_str(),
-        )
-        .bind(id)
-        .execute(self.pool.clone().as_ref())
-        .await?;
-
-        match result.rows_affected() {
-            0 => Err(ErrorKinsper::NotFound("User not found.".to_string())),
-            _ => Ok(result.rows_affected()),
-        }
-    }
-
-    pub async fn delete_user(&self, id: &str) -> Result<u64, ErrorKinsper> {
-        self.debug_thread();
-
-        let result = sqlx::query(
-            r#"
-            DELETE FROM users 
-            WHERE id = ?"#,
-        )
-        .bind(id)
-        .execute(self.pool.clone().as_ref())
-        .await?;
-
-        match result.rows_affected() {
-            0 => Err(ErrorKinsper::NotFound("User not found.".to_string())),
-            _ => Ok(result.rows_affected()),
-        }
-    }
-}
-
 #[cfg(test)]
-pub mod handler_tests {
+mod handler_tests {
     use std::sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
@@ -165,33 +9,39 @@ pub mod handler_tests {
 
     use crate::data::{
         context::Database,
-        scheme::{CreateUserScheme, UpdateUserSchema},
+        migrations::SchemaMigrations,
+        repository::UserRepository,
+        scheme::{CreateUserScheme, GetUsersScheme, UpdateUserSchema},
+        sql::sql_tests,
     };
 
     // Este mecanismo es para que se limpie la tabla al final de todos los tests
     // Como rust ejecuta los tests en paralelo (y yo quiero aprovechar eso) entonces
     // necesito que solo 1 test se encargue de limpiar la tabla
     // Se valida el comportamiento viendo el print "cargo test -- --show-output"
-    // Esto se arreglaria con Mocks ...
-    pub const NUMBER_TESTS: usize = 14; // contabilizar TODOS los tests del sistema
-    pub static TEST_COUNTER: AtomicUsize = AtomicUsize::new(NUMBER_TESTS);
+    // Los tests de handler_server.rs usan InMemoryDatabase, asi que solo cuentan
+    // los tests de este modulo. Requieren MySQL, por eso estan marcados como ignore
+    // y se corren con "make test" (cargo test -- --include-ignored)
+    const NUMBER_TESTS: usize = 16; // contabilizar TODOS los tests contra MySQL
+    static TEST_COUNTER: AtomicUsize = AtomicUsize::new(NUMBER_TESTS);
     async fn setup() -> sqlx::Result<Arc<Database>> {
         dotenv().ok();
         let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
         let db_context = Arc::new(Database::connect(&database_url).await.unwrap());
-        db_context.create_table().await.unwrap();
+        db_context.migrate_up().await.unwrap();
         Ok(db_context)
     }
 
     async fn teardown(db_context: Arc<Database>) -> sqlx::Result<()> {
         if TEST_COUNTER.fetch_sub(1, Ordering::SeqCst) == 1 {
             println!("Dropping table!");
-            db_context.drop_table().await.unwrap();
+            db_context.migrate_reset().await.unwrap();
         }
         Ok(())
     }
 
     #[tokio::test]
+    #[ignore = "requires a running MySQL"]
     async fn test01_when_get_user_by_id_given_inexistent_id_then_returns_error() -> sqlx::Result<()>
     {
         let db_context = setup().await?;
@@ -204,14 +54,15 @@ pub mod handler_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a running MySQL"]
     async fn test02_when_add_user_given_valid_user_then_can_get_that_user() -> sqlx::Result<()> {
         let db_context = setup().await?;
         let new_user = crate::data::scheme::CreateUserScheme {
             id: "15".to_string(),
             name: "Fede".to_string(),
-            mail: "fede@gmail.com".to_string(),
+            mail: "fede15@gmail.com".to_string(),
         };
-        db_context.add_user(&new_user).await.unwrap();
+        db_context.add_user(&new_user, "test").await.unwrap();
 
         let user_inserted = db_context.get_user_by_id("15").await.unwrap();
 
@@ -221,6 +72,7 @@ pub mod handler_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a running MySQL"]
     async fn test03_when_get_users_given_limit_then_returns_limited_number_of_users(
     ) -> sqlx::Result<()> {
         let db_context = setup().await?;
@@ -244,10 +96,13 @@ pub mod handler_tests {
         ];
 
         for user in new_users {
-            db_context.add_user(&user).await.unwrap();
+            db_context.add_user(&user, "test").await.unwrap();
         }
 
-        let users = db_context.get_users(Some(2)).await.unwrap();
+        let users = db_context
+            .get_users(&GetUsersScheme::new().with_limit(2))
+            .await
+            .unwrap();
 
         assert_eq!(users.len(), 2);
         teardown(db_context).await.unwrap();
@@ -255,6 +110,7 @@ pub mod handler_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a running MySQL"]
     async fn test04_when_update_user_given_valid_id_and_schema_then_updated_successfully(
     ) -> sqlx::Result<()> {
         let db_context = setup().await?;
@@ -264,7 +120,7 @@ pub mod handler_tests {
             name: "Jorge".to_string(),
             mail: "jorge@gmail.com".to_string(),
         };
-        db_context.add_user(&new_user).await.unwrap();
+        db_context.add_user(&new_user, "test").await.unwrap();
 
         let updated_user = UpdateUserSchema::new()
             .with_name("Jorge Updated".to_string())
@@ -272,7 +128,10 @@ pub mod handler_tests {
             .finalize()
             .unwrap();
 
-        db_context.update_user("9494", &updated_user).await.unwrap();
+        db_context
+            .update_user("9494", &updated_user, "test")
+            .await
+            .unwrap();
 
         let user_updated = db_context.get_user_by_id("9494").await.unwrap();
 
@@ -284,6 +143,7 @@ pub mod handler_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a running MySQL"]
     async fn test05_when_update_user_ineexistent_id_then_returns_error() -> sqlx::Result<()> {
         let db_context = setup().await?;
 
@@ -293,7 +153,7 @@ pub mod handler_tests {
             .finalize()
             .unwrap();
 
-        let result = db_context.update_user("9491", &updated_user).await;
+        let result = db_context.update_user("9491", &updated_user, "test").await;
 
         assert!(result.is_err());
         teardown(db_context).await.unwrap();
@@ -301,10 +161,11 @@ pub mod handler_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a running MySQL"]
     async fn test06_when_delete_user_given_inexistent_id_then_returns_error() -> sqlx::Result<()> {
         let db_context = setup().await?;
 
-        let result = db_context.delete_user("9492").await;
+        let result = db_context.delete_user("9492", None, "test").await;
 
         assert!(result.is_err());
         teardown(db_context).await.unwrap();
@@ -312,6 +173,7 @@ pub mod handler_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a running MySQL"]
     async fn test07_when_delete_user_given_valid_id_then_deleted_successfully() -> sqlx::Result<()>
     {
         let db_context = setup().await?;
@@ -321,9 +183,9 @@ pub mod handler_tests {
             name: "Luis".to_string(),
             mail: "luis@gmail.com".to_string(),
         };
-        db_context.add_user(&new_user).await.unwrap();
+        db_context.add_user(&new_user, "test").await.unwrap();
 
-        db_context.delete_user("25").await.unwrap();
+        db_context.delete_user("25", None, "test").await.unwrap();
 
         let deleted_user = db_context.get_user_by_id("25").await;
 
@@ -331,4 +193,196 @@ pub mod handler_tests {
         teardown(db_context).await.unwrap();
         Ok(())
     }
+
+    #[tokio::test]
+    #[ignore = "requires a running MySQL"]
+    async fn test08_when_update_user_given_names_with_quotes_then_stored_verbatim(
+    ) -> sqlx::Result<()> {
+        let db_context = setup().await?;
+
+        let new_user = CreateUserScheme {
+            id: "26".to_string(),
+            name: "Luis".to_string(),
+            mail: "luis26@gmail.com".to_string(),
+        };
+        db_context.add_user(&new_user, "test").await.unwrap();
+
+        for name in ["O'Brien", "'; DROP TABLE users; --"] {
+            let updated_user = UpdateUserSchema::new()
+                .with_name(name.to_string())
+                .finalize()
+                .unwrap();
+            db_context
+                .update_user("26", &updated_user, "test")
+                .await
+                .unwrap();
+
+            let user_updated = db_context.get_user_by_id("26").await.unwrap();
+            assert_eq!(user_updated.name, name);
+        }
+
+        teardown(db_context).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running MySQL"]
+    async fn test09_when_get_users_given_text_query_then_ranks_matches_by_score() -> sqlx::Result<()>
+    {
+        let db_context = setup().await?;
+        // Terminos que no usa ningun otro test, la tabla es compartida
+        for (id, name) in [
+            ("70", "Zorvan Quillet"),
+            ("71", "Zorvanio Quilletson"),
+            ("72", "Zorvan Marsh"),
+        ] {
+            let user = CreateUserScheme {
+                id: id.to_string(),
+                name: name.to_string(),
+                mail: format!("{}@mail.com", id),
+            };
+            db_context.add_user(&user, "test").await.unwrap();
+        }
+
+        let query = GetUsersScheme::new().with_text_query(Some("zorv QUILLET".to_string()));
+        let users = db_context.get_users(&query).await.unwrap();
+        let mut ids: Vec<&str> = users.iter().map(|user| user.id.as_str()).collect();
+        assert!(users.iter().all(|user| user.score.is_some()));
+        assert!(users.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        ids.sort();
+        assert_eq!(ids, vec!["70", "71"]);
+
+        // Paginando de a uno por (score, id) se recorren los mismos usuarios
+        let first = db_context
+            .get_users(&query.clone().with_limit(1))
+            .await
+            .unwrap();
+        let second = db_context
+            .get_users(
+                &query
+                    .clone()
+                    .with_limit(1)
+                    .with_after_id(first[0].id.clone())
+                    .with_after_score(first[0].score.unwrap()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(second.len(), 1);
+        assert_ne!(first[0].id, second[0].id);
+
+        teardown(db_context).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running MySQL"]
+    async fn test10_when_delete_user_then_hidden_until_restored() -> sqlx::Result<()> {
+        let db_context = setup().await?;
+
+        sql_tests::when_delete_user_then_hidden_until_restored(db_context.as_ref(), "80").await;
+
+        teardown(db_context).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running MySQL"]
+    async fn test11_when_purge_deleted_users_then_only_removes_rows_deleted_before_retention(
+    ) -> sqlx::Result<()> {
+        let db_context = setup().await?;
+
+        sql_tests::when_purge_deleted_users_then_only_removes_rows_deleted_before_retention(
+            db_context.as_ref(),
+            "81",
+            "82",
+        )
+        .await;
+
+        teardown(db_context).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running MySQL"]
+    async fn test12_when_get_users_given_time_ranges_then_filters_by_created_and_updated_at(
+    ) -> sqlx::Result<()> {
+        let db_context = setup().await?;
+
+        sql_tests::when_get_users_given_time_ranges_then_filters_by_created_and_updated_at(
+            db_context.as_ref(),
+            "83",
+            "84",
+        )
+        .await;
+
+        teardown(db_context).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running MySQL"]
+    async fn test13_when_write_given_stale_expected_version_then_returns_version_mismatch(
+    ) -> sqlx::Result<()> {
+        let db_context = setup().await?;
+
+        sql_tests::when_write_given_stale_expected_version_then_returns_version_mismatch(
+            db_context.as_ref(),
+            "85",
+            "86",
+        )
+        .await;
+
+        teardown(db_context).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running MySQL"]
+    async fn test14_when_mutate_users_then_audit_events_are_recorded_with_caller_and_values(
+    ) -> sqlx::Result<()> {
+        let db_context = setup().await?;
+
+        sql_tests::when_mutate_users_then_audit_events_are_recorded_with_caller_and_values(
+            db_context.as_ref(),
+            "87",
+        )
+        .await;
+
+        teardown(db_context).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running MySQL"]
+    async fn test15_when_update_user_given_missing_id_and_taken_mail_then_returns_not_found(
+    ) -> sqlx::Result<()> {
+        let db_context = setup().await?;
+
+        sql_tests::when_update_user_given_missing_id_and_taken_mail_then_returns_not_found(
+            db_context.as_ref(),
+            "88",
+            "89",
+        )
+        .await;
+
+        teardown(db_context).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running MySQL"]
+    async fn test16_when_get_users_given_text_query_with_tied_scores_then_pages_by_id(
+    ) -> sqlx::Result<()> {
+        let db_context = setup().await?;
+
+        sql_tests::when_get_users_given_text_query_with_tied_scores_then_pages_by_id(
+            db_context.as_ref(),
+            "Quorvex",
+            &["t1", "t2", "t3", "t4", "t5"],
+        )
+        .await;
+
+        teardown(db_context).await.unwrap();
+        Ok(())
+    }
 }
@@ -0,0 +1,875 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+
+use crate::errors::ErrorKinsper;
+use crate::{normalize_mail, MailLocalPartPolicy};
+
+use super::{
+    model::{AuditEventModel, AuditOperation, UserModel},
+    repository::{UserRepository, UserStream},
+    scheme::{
+        AuditEventScheme, CreateUserScheme, GetAuditEventsScheme, GetUsersScheme, UpdateUserSchema,
+    },
+};
+
+// Usuarios y auditoria quedan bajo el mismo lock, asi cada operacion y su evento
+// se escriben de forma atomica como en la transaccion de los backends SQL
+#[derive(Debug, Default)]
+struct MemoryStore {
+    users: BTreeMap<String, UserModel>,
+    audit: Vec<AuditEventModel>,
+    // Copias hechas por reset_table, por nombre de tabla
+    snapshots: BTreeMap<String, BTreeMap<String, UserModel>>,
+}
+
+impl MemoryStore {
+    fn record(&mut self, event: AuditEventScheme) {
+        self.audit.push(AuditEventModel {
+            id: self.audit.len() as i64 + 1,
+            operation: event.operation.as_str().to_string(),
+            user_id: event.user_id,
+            before_value: event.before_value,
+            after_value: event.after_value,
+            caller: event.caller,
+            created_at: event.created_at,
+        });
+    }
+
+    // Mismo control que los indices unicos de las bases sobre id y mail. current_id
+    // es el usuario que se esta actualizando, que no colisiona consigo mismo
+    fn check_unique(
+        &self,
+        current_id: Option<&str>,
+        id: Option<&str>,
+        mail: Option<&str>,
+    ) -> Result<(), ErrorKinsper> {
+        if let Some(id) = id.filter(|id| Some(*id) != current_id) {
+            if self.users.contains_key(id) {
+                return Err(ErrorKinsper::already_exists("id"));
+            }
+        }
+        if let Some(mail) = mail {
+            let taken = self
+                .users
+                .values()
+                .any(|user| user.mail == mail && Some(user.id.as_str()) != current_id);
+            if taken {
+                return Err(ErrorKinsper::already_exists("mail"));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Backend en memoria, pensado para tests y para embeber el servicio sin MySQL.
+// Replica los mismos errores que devuelve la capa de MySQL (ver errors.rs).
+#[derive(Debug, Default)]
+pub struct InMemoryDatabase {
+    store: RwLock<MemoryStore>,
+}
+
+impl InMemoryDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Usuarios copiados a snapshot_table por reset_table
+    pub fn snapshot(&self, snapshot_table: &str) -> Result<Vec<UserModel>, ErrorKinsper> {
+        self.read()?
+            .snapshots
+            .get(snapshot_table)
+            .map(|users| users.values().cloned().collect())
+            .ok_or_else(|| ErrorKinsper::NotFound(format!("Table {} not found", snapshot_table)))
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, MemoryStore>, ErrorKinsper> {
+        self.store
+            .read()
+            .map_err(|_| ErrorKinsper::InternalServer("In-memory store poisoned".to_string()))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, MemoryStore>, ErrorKinsper> {
+        self.store
+            .write()
+            .map_err(|_| ErrorKinsper::InternalServer("In-memory store poisoned".to_string()))
+    }
+
+    fn select(&self, query: &GetUsersScheme) -> Result<Vec<UserModel>, ErrorKinsper> {
+        let store = self.read()?;
+        let ranked = query.is_ranked();
+        let mut users: Vec<UserModel> = store
+            .users
+            .values()
+            .filter(|user| query.include_deleted || user.deleted_at.is_none())
+            .filter(|user| query.matches_time_range(user))
+            .filter(|user| query.matches_search(user))
+            .filter_map(|user| match query.text_score(user) {
+                Some(score) if ranked => Some(UserModel {
+                    score: Some(score),
+                    ..user.clone()
+                }),
+                None if ranked => None,
+                _ => Some(user.clone()),
+            })
+            .filter(|user| query.is_after_cursor(user))
+            .collect();
+        users.sort_by(|a, b| query.compare(a, b));
+        users.truncate(query.limit() as usize);
+
+        Ok(users)
+    }
+}
+
+fn check_version(user: &UserModel, expected_version: Option<i64>) -> Result<(), ErrorKinsper> {
+    match expected_version {
+        Some(expected_version) if expected_version != user.version => {
+            Err(ErrorKinsper::VersionMismatch(format!(
+                "Version mismatch: expected {}, current {}",
+                expected_version, user.version
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[async_trait]
+impl UserRepository for InMemoryDatabase {
+    async fn add_user(
+        &self,
+        user: &CreateUserScheme,
+        caller: &str,
+    ) -> Result<UserModel, ErrorKinsper> {
+        let mut store = self.write()?;
+
+        store.check_unique(None, Some(&user.id), Some(&user.mail))?;
+
+        let now = Utc::now();
+        let created = UserModel {
+            id: user.id.clone(),
+            name: user.name.clone(),
+            mail: user.mail.clone(),
+            deleted_at: None,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            score: None,
+        };
+        let event = AuditEventScheme::new(
+            AuditOperation::Create,
+            Some(&user.id),
+            None,
+            Some(&created),
+            caller,
+        )?;
+        store.users.insert(user.id.clone(), created.clone());
+        store.record(event);
+
+        Ok(created)
+    }
+
+    async fn get_users(&self, query: &GetUsersScheme) -> Result<Vec<UserModel>, ErrorKinsper> {
+        let result = self.select(query)?;
+
+        if result.is_empty() {
+            Err(ErrorKinsper::NotFound("No users found.".to_string()))
+        } else {
+            Ok(result)
+        }
+    }
+
+    fn stream_users(&self, query: &GetUsersScheme) -> UserStream {
+        let result = match self.select(query) {
+            Ok(users) => users.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+        futures::stream::iter(result).boxed()
+    }
+
+    async fn get_user_by_id(&self, id: &str) -> Result<UserModel, ErrorKinsper> {
+        self.read()?
+            .users
+            .get(id)
+            .filter(|user| user.deleted_at.is_none())
+            .cloned()
+            .ok_or_else(|| ErrorKinsper::NotFound("Error user not found".to_string()))
+    }
+
+    async fn get_user_by_mail(&self, mail: &str) -> Result<UserModel, ErrorKinsper> {
+        self.read()?
+            .users
+            .values()
+            .find(|user| user.mail == mail && user.deleted_at.is_none())
+            .cloned()
+            .ok_or_else(|| ErrorKinsper::NotFound("Error user not found".to_string()))
+    }
+
+    async fn update_user(
+        &self,
+        id: &str,
+        user: &UpdateUserSchema,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        if user.fields().is_empty() {
+            return Err(ErrorKinsper::UpdateSchemeError(
+                "No fields to update.".to_string(),
+            ));
+        }
+
+        let mut store = self.write()?;
+
+        // Mismo orden que en SQL: el UPDATE solo choca con los indices unicos si
+        // encontro la fila con la version esperada
+        let before = match store.users.get(id).filter(|user| user.deleted_at.is_none()) {
+            None => return Err(ErrorKinsper::NotFound("User not found.".to_string())),
+            Some(current) => {
+                check_version(current, user.expected_version)?;
+                current.clone()
+            }
+        };
+
+        store.check_unique(Some(id), user.id.as_deref(), user.mail.as_deref())?;
+
+        let mut updated = before.clone();
+        if let Some(new_id) = &user.id {
+            updated.id = new_id.clone();
+        }
+        if let Some(name) = &user.name {
+            updated.name = name.clone();
+        }
+        if let Some(mail) = &user.mail {
+            updated.mail = mail.clone();
+        }
+        updated.updated_at = Utc::now();
+        updated.version += 1;
+
+        let event = AuditEventScheme::new(
+            AuditOperation::Update,
+            Some(id),
+            Some(&before),
+            Some(&updated),
+            caller,
+        )?;
+        store.users.remove(id);
+        store.users.insert(updated.id.clone(), updated);
+        store.record(event);
+
+        Ok(1)
+    }
+
+    async fn delete_user(
+        &self,
+        id: &str,
+        expected_version: Option<i64>,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        let mut store = self.write()?;
+        let user = store
+            .users
+            .get_mut(id)
+            .filter(|user| user.deleted_at.is_none())
+            .ok_or_else(|| ErrorKinsper::NotFound("User not found.".to_string()))?;
+        check_version(user, expected_version)?;
+
+        let before = user.clone();
+        let now = Utc::now();
+        user.deleted_at = Some(now);
+        user.updated_at = now;
+        user.version += 1;
+
+        let event = AuditEventScheme::new(
+            AuditOperation::Delete,
+            Some(id),
+            Some(&before),
+            Some(user),
+            caller,
+        )?;
+        store.record(event);
+        Ok(1)
+    }
+
+    async fn restore_user(&self, id: &str, caller: &str) -> Result<u64, ErrorKinsper> {
+        let mut store = self.write()?;
+        let user = store
+            .users
+            .get_mut(id)
+            .filter(|user| user.deleted_at.is_some())
+            .ok_or_else(|| ErrorKinsper::NotFound("Deleted user not found.".to_string()))?;
+
+        let before = user.clone();
+        user.deleted_at = None;
+        user.updated_at = Utc::now();
+        user.version += 1;
+
+        let event = AuditEventScheme::new(
+            AuditOperation::Restore,
+            Some(id),
+            Some(&before),
+            Some(user),
+            caller,
+        )?;
+        store.record(event);
+        Ok(1)
+    }
+
+    async fn purge_deleted_users(
+        &self,
+        deleted_before: DateTime<Utc>,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        let mut store = self.write()?;
+        let purged: Vec<UserModel> = store
+            .users
+            .values()
+            .filter(|user| user.deleted_at.is_some_and(|at| at < deleted_before))
+            .cloned()
+            .collect();
+
+        for user in &purged {
+            let event = AuditEventScheme::new(
+                AuditOperation::Purge,
+                Some(&user.id),
+                Some(user),
+                None,
+                caller,
+            )?;
+            store.users.remove(&user.id);
+            store.record(event);
+        }
+
+        Ok(purged.len() as u64)
+    }
+
+    async fn reset_table(
+        &self,
+        snapshot_table: Option<&str>,
+        caller: &str,
+    ) -> Result<(), ErrorKinsper> {
+        let mut store = self.write()?;
+        let event = AuditEventScheme::new(AuditOperation::Reset, None, None, None, caller)?;
+        if let Some(snapshot_table) = snapshot_table {
+            if store.snapshots.contains_key(snapshot_table) {
+                return Err(ErrorKinsper::AlreadyExists(format!(
+                    "Table {} already exists",
+                    snapshot_table
+                )));
+            }
+        }
+        let users = std::mem::take(&mut store.users);
+        if let Some(snapshot_table) = snapshot_table {
+            store.snapshots.insert(snapshot_table.to_string(), users);
+        }
+        store.record(event);
+        Ok(())
+    }
+
+    // Se calculan todos los mails antes de cambiar ninguno, asi un duplicado no deja
+    // la normalizacion a medias, igual que el rollback de los backends SQL
+    async fn normalize_mails(
+        &self,
+        policy: MailLocalPartPolicy,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        let mut store = self.write()?;
+        let mails: BTreeMap<String, String> = store
+            .users
+            .values()
+            .map(|user| (user.id.clone(), normalize_mail(&user.mail, policy)))
+            .collect();
+        let mut unique = BTreeSet::new();
+        if !mails.values().all(|mail| unique.insert(mail)) {
+            return Err(ErrorKinsper::already_exists("mail"));
+        }
+
+        let now = Utc::now();
+        let mut normalized = 0;
+        for (id, mail) in mails {
+            let Some(user) = store.users.get_mut(&id) else {
+                continue;
+            };
+            if user.mail == mail {
+                continue;
+            }
+            let before = user.clone();
+            user.mail = mail;
+            user.updated_at = now;
+            user.version += 1;
+            let after = user.clone();
+            let event = AuditEventScheme::new(
+                AuditOperation::Update,
+                Some(&id),
+                Some(&before),
+                Some(&after),
+                caller,
+            )?;
+            store.record(event);
+            normalized += 1;
+        }
+        Ok(normalized)
+    }
+
+    async fn list_audit_events(
+        &self,
+        query: &GetAuditEventsScheme,
+    ) -> Result<Vec<AuditEventModel>, ErrorKinsper> {
+        Ok(self
+            .read()?
+            .audit
+            .iter()
+            .filter(|event| query.matches(event))
+            .take(query.limit() as usize)
+            .cloned()
+            .collect())
+    }
+    async fn last_audit_event_id(&self) -> Result<Option<i64>, ErrorKinsper> {
+        Ok(self.read()?.audit.last().map(|event| event.id))
+    }
+}
+
+#[cfg(test)]
+mod memory_tests {
+    use crate::data::{
+        memory::InMemoryDatabase,
+        model::AuditOperation,
+        repository::UserRepository,
+        scheme::{
+            CreateUserScheme, GetAuditEventsScheme, GetUsersScheme, UpdateUserSchema, UserSortField,
+        },
+        sql::sql_tests,
+    };
+    use crate::errors::ErrorKinsper;
+    use crate::MailLocalPartPolicy;
+
+    fn new_user(id: &str, name: &str, mail: &str) -> CreateUserScheme {
+        CreateUserScheme {
+            id: id.to_string(),
+            name: name.to_string(),
+            mail: mail.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test01_when_add_user_given_duplicated_id_then_returns_already_exists() {
+        let db_context = InMemoryDatabase::new();
+        db_context
+            .add_user(&new_user("1", "Fede", "fede@gmail.com"), "test")
+            .await
+            .unwrap();
+
+        let result = db_context
+            .add_user(&new_user("1", "Otro", "otro@gmail.com"), "test")
+            .await;
+
+        assert!(matches!(result, Err(ErrorKinsper::AlreadyExists(_))));
+    }
+
+    #[tokio::test]
+    async fn test02_when_get_users_given_limit_then_returns_limited_number_of_users() {
+        let db_context = InMemoryDatabase::new();
+        for id in ["20", "21", "23"] {
+            db_context
+                .add_user(
+                    &new_user(id, "User", &format!("user{}@example.com", id)),
+                    "test",
+                )
+                .await
+                .unwrap();
+        }
+
+        let users = db_context
+            .get_users(&GetUsersScheme::new().with_limit(2))
+            .await
+            .unwrap();
+
+        assert_eq!(users.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test03_when_get_users_given_empty_store_then_returns_not_found() {
+        let db_context = InMemoryDatabase::new();
+
+        let result = db_context.get_users(&GetUsersScheme::new()).await;
+
+        assert!(matches!(result, Err(ErrorKinsper::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test04_when_update_user_given_valid_id_and_schema_then_updated_successfully() {
+        let db_context = InMemoryDatabase::new();
+        db_context
+            .add_user(&new_user("9494", "Jorge", "jorge@gmail.com"), "test")
+            .await
+            .unwrap();
+
+        let updated_user = UpdateUserSchema::new()
+            .with_name("Jorge Updated".to_string())
+            .finalize()
+            .unwrap();
+        db_context
+            .update_user("9494", &updated_user, "test")
+            .await
+            .unwrap();
+
+        let user_updated = db_context.get_user_by_id("9494").await.unwrap();
+        assert_eq!(user_updated.name, "Jorge Updated");
+        assert_eq!(user_updated.mail, "jorge@gmail.com");
+    }
+
+    #[tokio::test]
+    async fn test05_when_update_user_given_inexistent_id_then_returns_not_found() {
+        let db_context = InMemoryDatabase::new();
+
+        let updated_user = UpdateUserSchema::new()
+            .with_name("Jorge Updated".to_string())
+            .finalize()
+            .unwrap();
+        let result = db_context.update_user("9491", &updated_user, "test").await;
+
+        assert!(matches!(result, Err(ErrorKinsper::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test06_when_delete_user_given_valid_id_then_deleted_successfully() {
+        let db_context = InMemoryDatabase::new();
+        db_context
+            .add_user(&new_user("25", "Luis", "luis@gmail.com"), "test")
+            .await
+            .unwrap();
+
+        db_context.delete_user("25", None, "test").await.unwrap();
+
+        assert!(db_context.get_user_by_id("25").await.is_err());
+        assert!(db_context.delete_user("25", None, "test").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test07_when_reset_table_then_store_is_empty_and_snapshot_keeps_the_users() {
+        let db_context = InMemoryDatabase::new();
+        db_context
+            .add_user(&new_user("30", "Ana", "ana@gmail.com"), "test")
+            .await
+            .unwrap();
+
+        db_context.reset_table(None, "test").await.unwrap();
+
+        assert!(db_context.get_users(&GetUsersScheme::new()).await.is_err());
+
+        db_context
+            .add_user(&new_user("31", "Eva", "eva@gmail.com"), "test")
+            .await
+            .unwrap();
+        db_context
+            .reset_table(Some("users_snapshot"), "test")
+            .await
+            .unwrap();
+        let snapshot = db_context.snapshot("users_snapshot").unwrap();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].id, "31");
+        assert!(db_context.get_users(&GetUsersScheme::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test08_when_restore_user_given_deleted_user_then_visible_again() {
+        let db_context = InMemoryDatabase::new();
+        db_context
+            .add_user(&new_user("31", "Ana", "ana@gmail.com"), "test")
+            .await
+            .unwrap();
+        db_context.delete_user("31", None, "test").await.unwrap();
+
+        db_context.restore_user("31", "test").await.unwrap();
+
+        assert!(db_context.get_user_by_id("31").await.is_ok());
+        assert!(matches!(
+            db_context.restore_user("31", "test").await,
+            Err(ErrorKinsper::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test09_when_update_user_then_only_updated_at_changes() {
+        let db_context = InMemoryDatabase::new();
+        db_context
+            .add_user(&new_user("32", "Ana", "ana@gmail.com"), "test")
+            .await
+            .unwrap();
+        let created = db_context.get_user_by_id("32").await.unwrap();
+        assert_eq!(created.created_at, created.updated_at);
+
+        let updated_user = UpdateUserSchema::new()
+            .with_name("Ana Maria".to_string())
+            .finalize()
+            .unwrap();
+        db_context
+            .update_user("32", &updated_user, "test")
+            .await
+            .unwrap();
+
+        let updated = db_context.get_user_by_id("32").await.unwrap();
+        assert_eq!(updated.created_at, created.created_at);
+        assert!(updated.updated_at >= created.updated_at);
+        let filtered = db_context
+            .get_users(&GetUsersScheme::new().with_updated_before(Some(created.created_at)))
+            .await;
+        assert!(matches!(filtered, Err(ErrorKinsper::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test10_when_update_user_given_stale_expected_version_then_returns_version_mismatch() {
+        let db_context = InMemoryDatabase::new();
+        db_context
+            .add_user(&new_user("33", "Ana", "ana@gmail.com"), "test")
+            .await
+            .unwrap();
+        let updated_user = UpdateUserSchema::new()
+            .with_mail("ana@hotmail.com".to_string())
+            .with_expected_version(Some(1))
+            .finalize()
+            .unwrap();
+        db_context
+            .update_user("33", &updated_user, "test")
+            .await
+            .unwrap();
+
+        let result = db_context.update_user("33", &updated_user, "test").await;
+
+        assert!(matches!(result, Err(ErrorKinsper::VersionMismatch(_))));
+        let user = db_context.get_user_by_id("33").await.unwrap();
+        assert_eq!(user.version, 2);
+        assert!(matches!(
+            db_context.delete_user("33", Some(1), "test").await,
+            Err(ErrorKinsper::VersionMismatch(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test11_when_restore_and_purge_users_then_audit_events_are_recorded() {
+        let db_context = InMemoryDatabase::new();
+        db_context
+            .add_user(&new_user("30", "Fede", "fede@gmail.com"), "alice")
+            .await
+            .unwrap();
+        db_context
+            .add_user(&new_user("31", "Juan", "juan@gmail.com"), "alice")
+            .await
+            .unwrap();
+        db_context.delete_user("30", None, "bob").await.unwrap();
+        db_context.restore_user("30", "bob").await.unwrap();
+        db_context.delete_user("31", None, "bob").await.unwrap();
+        db_context
+            .purge_deleted_users(chrono::Utc::now(), "admin")
+            .await
+            .unwrap();
+
+        let events = db_context
+            .list_audit_events(&GetAuditEventsScheme::new().with_user_id(Some("31".to_string())))
+            .await
+            .unwrap();
+        let operations: Vec<&str> = events.iter().map(|e| e.operation.as_str()).collect();
+        assert_eq!(operations, vec!["CREATE", "DELETE", "PURGE"]);
+        let purge = events.last().unwrap();
+        assert_eq!(purge.caller, "admin");
+        assert!(purge.before_value.as_ref().unwrap().contains("Juan"));
+        assert!(purge.after_value.is_none());
+
+        let restores = db_context
+            .list_audit_events(
+                &GetAuditEventsScheme::new().with_operation(Some(AuditOperation::Restore)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(restores.len(), 1);
+        assert_eq!(restores[0].user_id.as_deref(), Some("30"));
+    }
+
+    #[tokio::test]
+    async fn test12_when_write_given_taken_mail_then_already_exists_names_the_field() {
+        let db_context = InMemoryDatabase::new();
+        db_context
+            .add_user(&new_user("40", "Fede", "fede@gmail.com"), "test")
+            .await
+            .unwrap();
+        db_context
+            .add_user(&new_user("41", "Juan", "juan@gmail.com"), "test")
+            .await
+            .unwrap();
+
+        let result = db_context
+            .add_user(&new_user("42", "Fede", "fede@gmail.com"), "test")
+            .await;
+        assert_eq!(result.unwrap_err(), ErrorKinsper::already_exists("mail"));
+
+        let updated_user = UpdateUserSchema::new()
+            .with_mail("fede@gmail.com".to_string())
+            .finalize()
+            .unwrap();
+        let result = db_context.update_user("41", &updated_user, "test").await;
+        assert_eq!(result, Err(ErrorKinsper::already_exists("mail")));
+        db_context
+            .update_user("40", &updated_user, "test")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test13_when_get_users_given_search_and_sort_then_filters_and_orders() {
+        let db_context = InMemoryDatabase::new();
+        for (id, name, mail) in [
+            ("50", "John Doe", "john@doe.com"),
+            ("51", "jane doe", "jane@doe.com"),
+            ("52", "Johnny", "johnny@other.com"),
+        ] {
+            db_context
+                .add_user(&new_user(id, name, mail), "test")
+                .await
+                .unwrap();
+        }
+
+        let query = GetUsersScheme::new()
+            .with_name_contains(Some("DOE".to_string()))
+            .with_sort(UserSortField::Mail, false);
+        let users = db_context.get_users(&query).await.unwrap();
+        assert_eq!(
+            users
+                .iter()
+                .map(|user| user.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["51", "50"]
+        );
+
+        let query = GetUsersScheme::new()
+            .with_mail_domain(Some("Other.com".to_string()))
+            .with_name_prefix(Some("john".to_string()));
+        let users = db_context.get_users(&query).await.unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].id, "52");
+
+        let query = GetUsersScheme::new()
+            .with_sort(UserSortField::Name, false)
+            .with_after_id("50".to_string())
+            .with_after_key("John Doe".to_string());
+        let users = db_context.get_users(&query).await.unwrap();
+        assert_eq!(
+            users
+                .iter()
+                .map(|user| user.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["52", "51"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test14_when_get_users_given_text_query_then_whole_words_rank_first() {
+        let db_context = InMemoryDatabase::new();
+        for (id, name) in [("60", "Johnny Doe"), ("61", "John Doe"), ("62", "Jane Doe")] {
+            db_context
+                .add_user(&new_user(id, name, &format!("{}@mail.com", id)), "test")
+                .await
+                .unwrap();
+        }
+
+        let query = GetUsersScheme::new().with_text_query(Some("john doe".to_string()));
+        let users = db_context.get_users(&query).await.unwrap();
+        assert_eq!(
+            users
+                .iter()
+                .map(|user| user.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["61", "60"]
+        );
+        assert!(users[0].score > users[1].score);
+
+        let next = db_context
+            .get_users(
+                &query
+                    .clone()
+                    .with_after_id("61".to_string())
+                    .with_after_score(users[0].score.unwrap()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].id, "60");
+    }
+
+    #[tokio::test]
+    async fn test15_when_normalize_mails_given_duplicates_then_changes_none() {
+        let db_context = InMemoryDatabase::new();
+        for (id, mail) in [("70", "Ana@Mail.com"), ("71", "Bob@mail.com")] {
+            db_context
+                .add_user(&new_user(id, "name", mail), "test")
+                .await
+                .unwrap();
+        }
+
+        let normalized = db_context
+            .normalize_mails(MailLocalPartPolicy::Lowercase, "server")
+            .await
+            .unwrap();
+        assert_eq!(normalized, 2);
+        let user = db_context.get_user_by_id("70").await.unwrap();
+        assert_eq!(user.mail, "ana@mail.com");
+        assert_eq!(user.version, 2);
+
+        db_context
+            .add_user(&new_user("72", "name", "ANA@mail.com"), "test")
+            .await
+            .unwrap();
+        let result = db_context
+            .normalize_mails(MailLocalPartPolicy::Lowercase, "server")
+            .await;
+        assert!(matches!(result, Err(ErrorKinsper::AlreadyExists(_))));
+        assert_eq!(
+            db_context.get_user_by_id("72").await.unwrap().mail,
+            "ANA@mail.com"
+        );
+    }
+
+    // El backend en memoria cumple el mismo contrato que los backends SQL
+    #[tokio::test]
+    async fn test16_when_running_shared_sql_scenarios_then_all_pass() {
+        let db_context = InMemoryDatabase::new();
+
+        sql_tests::when_delete_user_then_hidden_until_restored(&db_context, "90").await;
+        sql_tests::when_purge_deleted_users_then_only_removes_rows_deleted_before_retention(
+            &db_context,
+            "91",
+            "92",
+        )
+        .await;
+        sql_tests::when_get_users_given_time_ranges_then_filters_by_created_and_updated_at(
+            &db_context,
+            "93",
+            "94",
+        )
+        .await;
+        sql_tests::when_write_given_stale_expected_version_then_returns_version_mismatch(
+            &db_context,
+            "95",
+            "96",
+        )
+        .await;
+        sql_tests::when_update_user_given_missing_id_and_taken_mail_then_returns_not_found(
+            &db_context,
+            "98",
+            "99",
+        )
+        .await;
+        sql_tests::when_get_users_given_text_query_with_tied_scores_then_pages_by_id(
+            &db_context,
+            "Quorvex",
+            &["t1", "t2", "t3", "t4", "t5"],
+        )
+        .await;
+        sql_tests::when_mutate_users_then_audit_events_are_recorded_with_caller_and_values(
+            &db_context,
+            "97",
+        )
+        .await;
+    }
+}
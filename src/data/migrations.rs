@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use sqlx::migrate::{Migrate, Migrator};
+
+use crate::errors::ErrorKinsper;
+
+use super::{
+    context::Database, postgres::PostgresDatabase, repository::url_scheme, sqlite::SqliteDatabase,
+};
+
+// Cada backend tiene su propio directorio de migraciones porque el DDL cambia
+// entre motores. Quedan embebidas en el binario en tiempo de compilacion.
+pub static MYSQL_MIGRATOR: Migrator = sqlx::migrate!("./migrations/mysql");
+pub static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+#[async_trait]
+pub trait SchemaMigrations: Send + Sync {
+    // Aplica todas las migraciones pendientes
+    async fn migrate_up(&self) -> Result<(), ErrorKinsper>;
+
+    // Revierte la ultima migracion aplicada, devolviendo su version
+    async fn migrate_down(&self) -> Result<Option<i64>, ErrorKinsper>;
+
+    // Revierte todas las migraciones aplicadas
+    async fn migrate_reset(&self) -> Result<(), ErrorKinsper>;
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, ErrorKinsper>;
+}
+
+pub async fn connect(database_url: &str) -> Result<Box<dyn SchemaMigrations>, ErrorKinsper> {
+    match url_scheme(database_url) {
+        "mysql" => Ok(Box::new(Database::connect(database_url).await?)),
+        "postgres" | "postgresql" => Ok(Box::new(PostgresDatabase::connect(database_url).await?)),
+        "sqlite" => Ok(Box::new(SqliteDatabase::connect(database_url).await?)),
+        scheme => Err(ErrorKinsper::InvalidUri(format!(
+            "Unsupported database url scheme: {:?}",
+            scheme
+        ))),
+    }
+}
+
+pub(crate) async fn run<C: Migrate>(migrator: &Migrator, conn: &mut C) -> Result<(), ErrorKinsper> {
+    migrator.run_direct(conn).await?;
+    Ok(())
+}
+
+// Misma logica que Migrator::undo, pero sobre una conexion ya adquirida y
+// devolviendo las versiones revertidas
+pub(crate) async fn undo<C: Migrate>(
+    migrator: &Migrator,
+    conn: &mut C,
+    target: i64,
+) -> Result<Vec<i64>, ErrorKinsper> {
+    if migrator.locking {
+        conn.lock().await?;
+    }
+    conn.ensure_migrations_table().await?;
+
+    let applied: HashSet<i64> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect();
+
+    let mut reverted = vec![];
+    for migration in migrator
+        .iter()
+        .rev()
+        .filter(|m| m.migration_type.is_down_migration())
+        .filter(|m| applied.contains(&m.version))
+        .filter(|m| m.version > target)
+    {
+        conn.revert(migration).await?;
+        reverted.push(migration.version);
+    }
+
+    if migrator.locking {
+        conn.unlock().await?;
+    }
+    Ok(reverted)
+}
+
+pub(crate) async fn undo_last<C: Migrate>(
+    migrator: &Migrator,
+    conn: &mut C,
+) -> Result<Option<i64>, ErrorKinsper> {
+    conn.ensure_migrations_table().await?;
+
+    let mut applied: Vec<i64> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect();
+    applied.sort_unstable();
+
+    if applied.pop().is_none() {
+        return Ok(None);
+    }
+    let target = applied.pop().unwrap_or(0);
+
+    Ok(undo(migrator, conn, target).await?.first().copied())
+}
+
+pub(crate) async fn status<C: Migrate>(
+    migrator: &Migrator,
+    conn: &mut C,
+) -> Result<Vec<MigrationStatus>, ErrorKinsper> {
+    conn.ensure_migrations_table().await?;
+
+    let applied: HashSet<i64> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect();
+
+    Ok(migrator
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied: applied.contains(&m.version),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod migrations_tests {
+    use crate::data::{migrations::SchemaMigrations, sqlite::SqliteDatabase};
+
+    async fn setup() -> SqliteDatabase {
+        SqliteDatabase::connect("sqlite::memory:").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test01_when_status_given_fresh_database_then_all_migrations_are_pending() {
+        let db_context = setup().await;
+
+        let status = db_context.migration_status().await.unwrap();
+
+        assert!(!status.is_empty());
+        assert!(status.iter().all(|migration| !migration.applied));
+    }
+
+    #[tokio::test]
+    async fn test02_when_migrate_up_then_all_migrations_are_applied() {
+        let db_context = setup().await;
+
+        db_context.migrate_up().await.unwrap();
+        // Volver a aplicar no debe fallar
+        db_context.migrate_up().await.unwrap();
+
+        let status = db_context.migration_status().await.unwrap();
+        assert!(status.iter().all(|migration| migration.applied));
+    }
+
+    #[tokio::test]
+    async fn test03_when_migrate_down_then_last_migration_is_reverted() {
+        let db_context = setup().await;
+        db_context.migrate_up().await.unwrap();
+        let last = db_context.migration_status().await.unwrap().last().cloned();
+
+        let reverted = db_context.migrate_down().await.unwrap();
+
+        assert_eq!(reverted, last.as_ref().map(|migration| migration.version));
+        let status = db_context.migration_status().await.unwrap();
+        assert!(!status.last().unwrap().applied);
+    }
+
+    #[tokio::test]
+    async fn test04_when_migrate_down_given_no_applied_migrations_then_returns_none() {
+        let db_context = setup().await;
+
+        let reverted = db_context.migrate_down().await.unwrap();
+
+        assert_eq!(reverted, None);
+    }
+}
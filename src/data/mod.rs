@@ -1,6 +1,31 @@
+use chrono::{DateTime, Utc};
+
 pub mod context;
 pub mod handler;
+pub mod memory;
+pub mod migrations;
 pub mod model;
+pub mod postgres;
+pub mod repository;
 pub mod scheme;
+pub mod sql;
+pub mod sqlite;
 
 pub const QUERY_LIMIT: u32 = 1024;
+pub const QUERY_STREAM_BUFFER: usize = 64;
+// Conexiones maximas del pool, el mismo valor por defecto que usa sqlx
+pub const DEFAULT_POOL_SIZE: u32 = 10;
+
+// Tabla nueva con la copia de users que hace reset_table, con la fecha hasta los
+// milisegundos: users_snapshot_20240131120000123
+pub fn snapshot_table_name(now: DateTime<Utc>) -> String {
+    format!("users_snapshot_{}", now.format("%Y%m%d%H%M%S%3f"))
+}
+
+pub(crate) fn debug_thread() {
+    log::debug!(
+        "[CURRENT_THREAD: {:?}] | [THREAD_NAME: {:?}]",
+        std::thread::current().id(),
+        std::thread::current().name().unwrap()
+    );
+}
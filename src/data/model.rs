@@ -1,9 +1,78 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
+use crate::errors::ErrorKinsper;
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
 #[allow(non_snake_case)]
 pub struct UserModel {
     pub id: String,
     pub name: String,
     pub mail: String,
+    // Los maneja el servidor, no se pueden modificar desde la API
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    // Se incrementa en cada escritura, para control de concurrencia optimista
+    pub version: i64,
+    // Soft delete: si tiene valor, el usuario esta borrado
+    pub deleted_at: Option<DateTime<Utc>>,
+    // Relevancia en las busquedas de texto completo, no es una columna de la tabla
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub score: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AuditOperation {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Purge,
+    Reset,
+}
+
+impl AuditOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOperation::Create => "CREATE",
+            AuditOperation::Update => "UPDATE",
+            AuditOperation::Delete => "DELETE",
+            AuditOperation::Restore => "RESTORE",
+            AuditOperation::Purge => "PURGE",
+            AuditOperation::Reset => "RESET",
+        }
+    }
+}
+
+impl std::str::FromStr for AuditOperation {
+    type Err = ErrorKinsper;
+
+    fn from_str(operation: &str) -> Result<Self, Self::Err> {
+        match operation {
+            "CREATE" => Ok(AuditOperation::Create),
+            "UPDATE" => Ok(AuditOperation::Update),
+            "DELETE" => Ok(AuditOperation::Delete),
+            "RESTORE" => Ok(AuditOperation::Restore),
+            "PURGE" => Ok(AuditOperation::Purge),
+            "RESET" => Ok(AuditOperation::Reset),
+            _ => Err(ErrorKinsper::InternalServer(format!(
+                "Unknown audit operation: {}",
+                operation
+            ))),
+        }
+    }
+}
+
+// Fila de user_audit. before_value y after_value son el UserModel serializado en
+// JSON, vacios cuando el usuario no existia antes o despues de la operacion.
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
+pub struct AuditEventModel {
+    pub id: i64,
+    pub operation: String,
+    pub user_id: Option<String>,
+    pub before_value: Option<String>,
+    pub after_value: Option<String>,
+    pub caller: String,
+    pub created_at: DateTime<Utc>,
 }
@@ -0,0 +1,306 @@
+use std::sync::Arc;
+
+use sqlx::{postgres::PgPoolOptions, Postgres};
+
+use crate::{data::DEFAULT_POOL_SIZE, errors::ErrorKinsper};
+
+use super::sql::SqlDatabase;
+
+pub type PostgresDatabase = SqlDatabase<Postgres>;
+
+impl PostgresDatabase {
+    pub async fn connect(sql_url: &str) -> Result<PostgresDatabase, ErrorKinsper> {
+        Self::connect_with_pool_size(sql_url, DEFAULT_POOL_SIZE).await
+    }
+
+    pub async fn connect_with_pool_size(
+        sql_url: &str,
+        pool_size: u32,
+    ) -> Result<PostgresDatabase, ErrorKinsper> {
+        let connection = PgPoolOptions::new()
+            .max_connections(pool_size)
+            .connect(sql_url)
+            .await
+            .map_err(|err| {
+                ErrorKinsper::ConnectionError(format!("Couldn't connect to the database: {}", err))
+            })?;
+        let pool = Arc::new(connection);
+
+        Ok(PostgresDatabase { pool })
+    }
+}
+
+#[cfg(test)]
+mod postgres_tests {
+    use crate::data::{
+        migrations::SchemaMigrations,
+        postgres::PostgresDatabase,
+        repository::{self, UserRepository},
+        scheme::{CreateUserScheme, GetUsersScheme, UpdateUserSchema},
+        sql::sql_tests,
+    };
+    use crate::errors::ErrorKinsper;
+    use chrono::{Duration, Utc};
+    use dotenv::dotenv;
+
+    // Cada test trabaja sobre ids propios, la tabla se comparte con el resto de tests
+    async fn setup() -> PostgresDatabase {
+        dotenv().ok();
+        let database_url =
+            std::env::var("POSTGRES_DATABASE_URL").expect("POSTGRES_DATABASE_URL must be set");
+        let db_context = PostgresDatabase::connect(&database_url).await.unwrap();
+        db_context.migrate_up().await.unwrap();
+        db_context
+    }
+
+    // delete_user es un soft delete, hay que purgar la fila para poder repetir los tests
+    async fn cleanup(db_context: &impl UserRepository, id: &str) {
+        db_context.delete_user(id, None, "test").await.unwrap();
+        db_context
+            .purge_deleted_users(Utc::now() + Duration::seconds(1), "test")
+            .await
+            .unwrap();
+    }
+
+    fn new_user(id: &str) -> CreateUserScheme {
+        CreateUserScheme {
+            id: id.to_string(),
+            name: "Fede".to_string(),
+            mail: format!("fede{}@gmail.com", id),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test01_when_add_user_given_duplicated_id_then_returns_already_exists() {
+        let db_context = setup().await;
+        db_context
+            .add_user(&new_user("pg15"), "test")
+            .await
+            .unwrap();
+
+        let result = db_context.add_user(&new_user("pg15"), "test").await;
+
+        assert!(matches!(result, Err(ErrorKinsper::AlreadyExists(_))));
+        cleanup(&db_context, "pg15").await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test02_when_get_user_by_id_given_inexistent_id_then_returns_not_found() {
+        let db_context = setup().await;
+
+        let result = db_context.get_user_by_id("pg12").await;
+
+        assert!(matches!(result, Err(ErrorKinsper::NotFound(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test03_when_update_user_given_valid_id_and_schema_then_updated_successfully() {
+        let db_context = setup().await;
+        db_context
+            .add_user(&new_user("pg9494"), "test")
+            .await
+            .unwrap();
+
+        let updated_user = UpdateUserSchema::new()
+            .with_name("Jorge Updated".to_string())
+            .with_mail("jorge_updated@gmail.com".to_string())
+            .finalize()
+            .unwrap();
+        db_context
+            .update_user("pg9494", &updated_user, "test")
+            .await
+            .unwrap();
+
+        let user_updated = db_context.get_user_by_id("pg9494").await.unwrap();
+        assert_eq!(user_updated.name, "Jorge Updated");
+        assert_eq!(user_updated.mail, "jorge_updated@gmail.com");
+        cleanup(&db_context, "pg9494").await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test04_when_delete_user_given_inexistent_id_then_returns_not_found() {
+        let db_context = setup().await;
+
+        let result = db_context.delete_user("pg9492", None, "test").await;
+
+        assert!(matches!(result, Err(ErrorKinsper::NotFound(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test05_when_connect_given_postgres_url_then_returns_repository() {
+        dotenv().ok();
+        let database_url =
+            std::env::var("POSTGRES_DATABASE_URL").expect("POSTGRES_DATABASE_URL must be set");
+        let db_context = repository::connect(&database_url).await.unwrap();
+        db_context.add_user(&new_user("pg1"), "test").await.unwrap();
+
+        let user = db_context.get_user_by_id("pg1").await.unwrap();
+
+        assert_eq!(user.id, "pg1");
+        cleanup(&db_context, "pg1").await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test06_when_update_user_given_names_with_quotes_then_stored_verbatim() {
+        let db_context = setup().await;
+        db_context
+            .add_user(&new_user("pg26"), "test")
+            .await
+            .unwrap();
+
+        for name in ["O'Brien", "'; DROP TABLE users; --"] {
+            let updated_user = UpdateUserSchema::new()
+                .with_name(name.to_string())
+                .finalize()
+                .unwrap();
+            db_context
+                .update_user("pg26", &updated_user, "test")
+                .await
+                .unwrap();
+
+            let user_updated = db_context.get_user_by_id("pg26").await.unwrap();
+            assert_eq!(user_updated.name, name);
+        }
+        cleanup(&db_context, "pg26").await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test07_when_get_users_given_text_query_then_ranks_matches_by_score() {
+        let db_context = setup().await;
+        // Terminos que no usa ningun otro test, la tabla es compartida
+        for (id, name) in [
+            ("pg70", "Zorvan Quillet"),
+            ("pg71", "Zorvanio Quilletson"),
+            ("pg72", "Zorvan Marsh"),
+        ] {
+            let user = CreateUserScheme {
+                id: id.to_string(),
+                name: name.to_string(),
+                mail: format!("{}@mail.com", id),
+            };
+            db_context.add_user(&user, "test").await.unwrap();
+        }
+
+        let query = GetUsersScheme::new().with_text_query(Some("zorv QUILLET".to_string()));
+        let users = db_context.get_users(&query).await.unwrap();
+        let mut ids: Vec<&str> = users.iter().map(|user| user.id.as_str()).collect();
+        assert!(users.iter().all(|user| user.score.is_some()));
+        assert!(users.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        ids.sort();
+        assert_eq!(ids, vec!["pg70", "pg71"]);
+
+        // Paginando de a uno por (score, id) se recorren los mismos usuarios
+        let first = db_context
+            .get_users(&query.clone().with_limit(1))
+            .await
+            .unwrap();
+        let second = db_context
+            .get_users(
+                &query
+                    .clone()
+                    .with_limit(1)
+                    .with_after_id(first[0].id.clone())
+                    .with_after_score(first[0].score.unwrap()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(second.len(), 1);
+        assert_ne!(first[0].id, second[0].id);
+
+        for id in ["pg70", "pg71", "pg72"] {
+            cleanup(&db_context, id).await;
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test08_when_delete_user_then_hidden_until_restored() {
+        let db_context = setup().await;
+
+        sql_tests::when_delete_user_then_hidden_until_restored(&db_context, "pg80").await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test09_when_purge_deleted_users_then_only_removes_rows_deleted_before_retention() {
+        let db_context = setup().await;
+
+        sql_tests::when_purge_deleted_users_then_only_removes_rows_deleted_before_retention(
+            &db_context,
+            "pg81",
+            "pg82",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test10_when_get_users_given_time_ranges_then_filters_by_created_and_updated_at() {
+        let db_context = setup().await;
+
+        sql_tests::when_get_users_given_time_ranges_then_filters_by_created_and_updated_at(
+            &db_context,
+            "pg83",
+            "pg84",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test11_when_write_given_stale_expected_version_then_returns_version_mismatch() {
+        let db_context = setup().await;
+
+        sql_tests::when_write_given_stale_expected_version_then_returns_version_mismatch(
+            &db_context,
+            "pg85",
+            "pg86",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test12_when_mutate_users_then_audit_events_are_recorded_with_caller_and_values() {
+        let db_context = setup().await;
+
+        sql_tests::when_mutate_users_then_audit_events_are_recorded_with_caller_and_values(
+            &db_context,
+            "pg87",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test13_when_update_user_given_missing_id_and_taken_mail_then_returns_not_found() {
+        let db_context = setup().await;
+
+        sql_tests::when_update_user_given_missing_id_and_taken_mail_then_returns_not_found(
+            &db_context,
+            "pg88",
+            "pg89",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Postgres"]
+    async fn test14_when_get_users_given_text_query_with_tied_scores_then_pages_by_id() {
+        let db_context = setup().await;
+
+        sql_tests::when_get_users_given_text_query_with_tied_scores_then_pages_by_id(
+            &db_context,
+            "Quorvex",
+            &["pgt1", "pgt2", "pgt3", "pgt4", "pgt5"],
+        )
+        .await;
+    }
+}
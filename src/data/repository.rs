@@ -0,0 +1,249 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+
+use crate::errors::ErrorKinsper;
+use crate::MailLocalPartPolicy;
+
+use super::{
+    context::Database,
+    migrations::SchemaMigrations,
+    model::{AuditEventModel, UserModel},
+    postgres::PostgresDatabase,
+    scheme::{CreateUserScheme, GetAuditEventsScheme, GetUsersScheme, UpdateUserSchema},
+    sqlite::SqliteDatabase,
+    DEFAULT_POOL_SIZE,
+};
+
+// Contrato que tiene que cumplir cualquier backend de almacenamiento de usuarios.
+// MyUserService es generico sobre este trait, asi se puede usar tanto la MySQL
+// real (Database) como el backend en memoria (InMemoryDatabase) en los tests.
+pub type UserStream = BoxStream<'static, Result<UserModel, ErrorKinsper>>;
+
+// Cada operacion que modifica usuarios registra un evento en user_audit dentro de
+// la misma transaccion, con caller como identidad de quien la pidio.
+#[async_trait]
+pub trait UserRepository: Send + Sync + 'static {
+    // Devuelve el usuario tal como quedo guardado, leido en la misma transaccion
+    async fn add_user(
+        &self,
+        user: &CreateUserScheme,
+        caller: &str,
+    ) -> Result<UserModel, ErrorKinsper>;
+
+    async fn get_users(&self, query: &GetUsersScheme) -> Result<Vec<UserModel>, ErrorKinsper>;
+
+    // Igual que get_users pero sin materializar el resultado completo
+    fn stream_users(&self, query: &GetUsersScheme) -> UserStream;
+
+    async fn get_user_by_id(&self, id: &str) -> Result<UserModel, ErrorKinsper>;
+
+    // mail tiene que llegar normalizado, igual que como se guarda
+    async fn get_user_by_mail(&self, mail: &str) -> Result<UserModel, ErrorKinsper>;
+
+    // Con user.expected_version solo se actualiza si la version actual coincide
+    async fn update_user(
+        &self,
+        id: &str,
+        user: &UpdateUserSchema,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper>;
+
+    // Soft delete: el usuario queda marcado como borrado y deja de aparecer en las lecturas
+    async fn delete_user(
+        &self,
+        id: &str,
+        expected_version: Option<i64>,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper>;
+
+    async fn restore_user(&self, id: &str, caller: &str) -> Result<u64, ErrorKinsper>;
+
+    // Borra definitivamente los usuarios marcados como borrados antes de deleted_before
+    async fn purge_deleted_users(
+        &self,
+        deleted_before: DateTime<Utc>,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper>;
+
+    // Borra todos los usuarios. El historial de user_audit se conserva. Con
+    // snapshot_table antes se copian los usuarios a esa tabla nueva
+    async fn reset_table(
+        &self,
+        snapshot_table: Option<&str>,
+        caller: &str,
+    ) -> Result<(), ErrorKinsper>;
+
+    // Guarda normalizados con policy los mails de todos los usuarios, incluidos los
+    // borrados, y devuelve cuantos cambiaron. Si dos mails quedan iguales falla por el
+    // indice unico sin cambiar ninguno
+    async fn normalize_mails(
+        &self,
+        policy: MailLocalPartPolicy,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper>;
+
+    async fn list_audit_events(
+        &self,
+        query: &GetAuditEventsScheme,
+    ) -> Result<Vec<AuditEventModel>, ErrorKinsper>;
+
+    // Id del ultimo evento de auditoria, None si todavia no hay eventos
+    async fn last_audit_event_id(&self) -> Result<Option<i64>, ErrorKinsper>;
+}
+
+#[async_trait]
+impl<R: UserRepository + ?Sized> UserRepository for Box<R> {
+    async fn add_user(
+        &self,
+        user: &CreateUserScheme,
+        caller: &str,
+    ) -> Result<UserModel, ErrorKinsper> {
+        (**self).add_user(user, caller).await
+    }
+
+    async fn get_users(&self, query: &GetUsersScheme) -> Result<Vec<UserModel>, ErrorKinsper> {
+        (**self).get_users(query).await
+    }
+
+    fn stream_users(&self, query: &GetUsersScheme) -> UserStream {
+        (**self).stream_users(query)
+    }
+
+    async fn get_user_by_id(&self, id: &str) -> Result<UserModel, ErrorKinsper> {
+        (**self).get_user_by_id(id).await
+    }
+
+    async fn get_user_by_mail(&self, mail: &str) -> Result<UserModel, ErrorKinsper> {
+        (**self).get_user_by_mail(mail).await
+    }
+
+    async fn update_user(
+        &self,
+        id: &str,
+        user: &UpdateUserSchema,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        (**self).update_user(id, user, caller).await
+    }
+
+    async fn delete_user(
+        &self,
+        id: &str,
+        expected_version: Option<i64>,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        (**self).delete_user(id, expected_version, caller).await
+    }
+
+    async fn restore_user(&self, id: &str, caller: &str) -> Result<u64, ErrorKinsper> {
+        (**self).restore_user(id, caller).await
+    }
+
+    async fn purge_deleted_users(
+        &self,
+        deleted_before: DateTime<Utc>,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        (**self).purge_deleted_users(deleted_before, caller).await
+    }
+
+    async fn reset_table(
+        &self,
+        snapshot_table: Option<&str>,
+        caller: &str,
+    ) -> Result<(), ErrorKinsper> {
+        (**self).reset_table(snapshot_table, caller).await
+    }
+
+    async fn normalize_mails(
+        &self,
+        policy: MailLocalPartPolicy,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        (**self).normalize_mails(policy, caller).await
+    }
+
+    async fn list_audit_events(
+        &self,
+        query: &GetAuditEventsScheme,
+    ) -> Result<Vec<AuditEventModel>, ErrorKinsper> {
+        (**self).list_audit_events(query).await
+    }
+
+    async fn last_audit_event_id(&self) -> Result<Option<i64>, ErrorKinsper> {
+        (**self).last_audit_event_id().await
+    }
+}
+
+// Cuando un UPDATE condicionado por version no afecta filas puede ser porque el
+// usuario no existe o porque la version no coincide. Con el usuario leido dentro
+// de la misma transaccion se devuelve el error correcto.
+pub(crate) fn version_mismatch_or(
+    current: Option<&UserModel>,
+    expected_version: Option<i64>,
+    not_found: ErrorKinsper,
+) -> ErrorKinsper {
+    match (expected_version, current) {
+        (Some(expected_version), Some(user)) => ErrorKinsper::VersionMismatch(format!(
+            "Version mismatch: expected {}, current {}",
+            expected_version, user.version
+        )),
+        _ => not_found,
+    }
+}
+
+pub(crate) fn url_scheme(database_url: &str) -> &str {
+    database_url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .unwrap_or_default()
+}
+
+// Nombre de la base de DATABASE_URL: mysql://host/users => users,
+// sqlite://data/users.db => users y sqlite::memory: => memory
+pub fn database_name(database_url: &str) -> &str {
+    let path = database_url
+        .split_once(':')
+        .map(|(_, rest)| rest)
+        .unwrap_or(database_url);
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let name = name.trim_matches(':');
+    name.split_once('.').map(|(stem, _)| stem).unwrap_or(name)
+}
+
+// Elige el backend segun el esquema de DATABASE_URL (mysql://, postgres:// o sqlite:)
+// y aplica las migraciones pendientes antes de devolverlo.
+pub async fn connect(database_url: &str) -> Result<Box<dyn UserRepository>, ErrorKinsper> {
+    connect_with_pool_size(database_url, DEFAULT_POOL_SIZE).await
+}
+
+pub async fn connect_with_pool_size(
+    database_url: &str,
+    pool_size: u32,
+) -> Result<Box<dyn UserRepository>, ErrorKinsper> {
+    match url_scheme(database_url) {
+        "mysql" => {
+            let db_context = Database::connect_with_pool_size(database_url, pool_size).await?;
+            db_context.migrate_up().await?;
+            Ok(Box::new(db_context))
+        }
+        "postgres" | "postgresql" => {
+            let db_context =
+                PostgresDatabase::connect_with_pool_size(database_url, pool_size).await?;
+            db_context.migrate_up().await?;
+            Ok(Box::new(db_context))
+        }
+        "sqlite" => {
+            let db_context =
+                SqliteDatabase::connect_with_pool_size(database_url, pool_size).await?;
+            db_context.migrate_up().await?;
+            Ok(Box::new(db_context))
+        }
+        scheme => Err(ErrorKinsper::InvalidUri(format!(
+            "Unsupported database url scheme: {:?}",
+            scheme
+        ))),
+    }
+}
@@ -1,6 +1,17 @@
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::errors::ErrorKinsper;
+use sqlx::{Database, Encode, MySql, Postgres, QueryBuilder, Sqlite, Type};
+
+use crate::{
+    data::{
+        model::{AuditEventModel, AuditOperation, UserModel},
+        QUERY_LIMIT,
+    },
+    errors::ErrorKinsper,
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 #[allow(non_snake_case)]
@@ -15,7 +26,8 @@ pub struct UpdateUserSchema {
     pub id: Option<String>,
     pub name: Option<String>,
     pub mail: Option<String>,
-    pub query_set: String,
+    // Si se indica, la actualizacion falla cuando la version actual es otra
+    pub expected_version: Option<i64>,
 }
 
 impl UpdateUserSchema {
@@ -24,14 +36,10 @@ impl UpdateUserSchema {
             id: None,
             name: None,
             mail: None,
-            query_set: String::new(),
+            expected_version: None,
         }
     }
 
-    pub fn query_set(&self) -> &String {
-        &self.query_set
-    }
-
     pub fn with_id(mut self, id: String) -> Self {
         self.id = Some(id);
         self
@@ -47,27 +55,752 @@ impl UpdateUserSchema {
         self
     }
 
+    pub fn with_expected_version(mut self, expected_version: Option<i64>) -> Self {
+        self.expected_version = expected_version;
+        self
+    }
+
     pub fn finalize(self) -> Result<Self, ErrorKinsper> {
-        let query_set = self.prepare_query_set()?;
-        Ok(UpdateUserSchema { query_set, ..self })
+        if self.fields().is_empty() {
+            return Err(ErrorKinsper::UpdateSchemeError(
+                "No fields to update.".to_string(),
+            ));
+        }
+
+        Ok(self)
     }
 
-    fn prepare_query_set(&self) -> Result<String, ErrorKinsper> {
-        let updates: Vec<String> = vec![
-            self.id.as_ref().map(|id| format!("id = '{}'", id)),
-            self.name.as_ref().map(|name| format!("name = '{}'", name)),
-            self.mail.as_ref().map(|mail| format!("mail = '{}'", mail)),
+    // Columnas a actualizar junto con su nuevo valor. Los nombres de columna son
+    // fijos y los valores se bindean siempre como parametros de la query.
+    pub fn fields(&self) -> Vec<(&'static str, &String)> {
+        vec![
+            self.id.as_ref().map(|id| ("id", id)),
+            self.name.as_ref().map(|name| ("name", name)),
+            self.mail.as_ref().map(|mail| ("mail", mail)),
         ]
         .into_iter()
         .flatten()
-        .collect();
+        .collect()
+    }
 
-        if updates.is_empty() {
-            return Err(ErrorKinsper::UpdateSchemeError(
-                "No fields to update.".to_string(),
-            ));
+    // Agrega al builder el SET de la query con cada valor como parametro bindeado,
+    // junto con el nuevo updated_at y el incremento de version
+    pub(crate) fn push_query_set<'args, DB>(
+        &self,
+        builder: &mut QueryBuilder<'args, DB>,
+        updated_at: DateTime<Utc>,
+    ) where
+        DB: Database,
+        String: Encode<'args, DB> + Type<DB>,
+        DateTime<Utc>: Encode<'args, DB> + Type<DB>,
+    {
+        builder.push(" SET ");
+        let mut set = builder.separated(", ");
+        for (column, value) in self.fields() {
+            set.push(format!("{} = ", column));
+            set.push_bind_unseparated(value.clone());
+        }
+        set.push("updated_at = ");
+        set.push_bind_unseparated(updated_at);
+        set.push("version = version + 1");
+    }
+
+    // Agrega al WHERE de la query la condicion sobre la version esperada
+    pub(crate) fn push_query_version<'args, DB>(&self, builder: &mut QueryBuilder<'args, DB>)
+    where
+        DB: Database,
+        i64: Encode<'args, DB> + Type<DB>,
+    {
+        if let Some(expected_version) = self.expected_version {
+            builder.push(" AND version = ").push_bind(expected_version);
+        }
+    }
+}
+
+// Columna por la que se ordenan los usuarios, desempatando siempre por id
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UserSortField {
+    #[default]
+    Id,
+    Name,
+    Mail,
+}
+
+impl UserSortField {
+    pub fn column(&self) -> &'static str {
+        match self {
+            UserSortField::Id => "id",
+            UserSortField::Name => "name",
+            UserSortField::Mail => "mail",
+        }
+    }
+
+    pub fn value<'a>(&self, user: &'a UserModel) -> &'a str {
+        match self {
+            UserSortField::Id => &user.id,
+            UserSortField::Name => &user.name,
+            UserSortField::Mail => &user.mail,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GetUsersScheme {
+    pub limit: Option<u32>,
+    // Keyset pagination: solo se devuelven usuarios que van despues de este id en
+    // el orden pedido. Si se ordena por otra columna, after_key es el valor de esa
+    // columna en el ultimo usuario de la pagina anterior
+    pub after_id: Option<String>,
+    pub after_key: Option<String>,
+    pub include_deleted: bool,
+    // Rangos de tiempo: el limite inferior es inclusivo y el superior exclusivo
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    // Busqueda: el nombre se compara sin distinguir mayusculas y el mail ya
+    // normalizado, como se guarda
+    pub name_contains: Option<String>,
+    pub name_prefix: Option<String>,
+    pub mail: Option<String>,
+    pub mail_domain: Option<String>,
+    pub sort_by: UserSortField,
+    pub descending: bool,
+    // Busqueda de texto completo sobre el nombre. Si tiene terminos, los usuarios
+    // se ordenan por relevancia (score) y se ignoran sort_by y descending
+    pub text_query: Option<String>,
+    // Cursor de la paginacion por relevancia: score del ultimo usuario de la pagina
+    pub after_score: Option<f64>,
+}
+
+impl GetUsersScheme {
+    pub fn new() -> Self {
+        GetUsersScheme {
+            limit: None,
+            after_id: None,
+            after_key: None,
+            include_deleted: false,
+            created_after: None,
+            created_before: None,
+            updated_after: None,
+            updated_before: None,
+            name_contains: None,
+            name_prefix: None,
+            mail: None,
+            mail_domain: None,
+            sort_by: UserSortField::Id,
+            descending: false,
+            text_query: None,
+            after_score: None,
+        }
+    }
+
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn with_after_id(mut self, after_id: String) -> Self {
+        self.after_id = Some(after_id);
+        self
+    }
+
+    pub fn with_after_key(mut self, after_key: String) -> Self {
+        self.after_key = Some(after_key);
+        self
+    }
+
+    pub fn with_include_deleted(mut self, include_deleted: bool) -> Self {
+        self.include_deleted = include_deleted;
+        self
+    }
+
+    pub fn with_created_after(mut self, created_after: Option<DateTime<Utc>>) -> Self {
+        self.created_after = created_after;
+        self
+    }
+
+    pub fn with_created_before(mut self, created_before: Option<DateTime<Utc>>) -> Self {
+        self.created_before = created_before;
+        self
+    }
+
+    pub fn with_updated_after(mut self, updated_after: Option<DateTime<Utc>>) -> Self {
+        self.updated_after = updated_after;
+        self
+    }
+
+    pub fn with_updated_before(mut self, updated_before: Option<DateTime<Utc>>) -> Self {
+        self.updated_before = updated_before;
+        self
+    }
+
+    pub fn with_name_contains(mut self, name_contains: Option<String>) -> Self {
+        self.name_contains = name_contains;
+        self
+    }
+
+    pub fn with_name_prefix(mut self, name_prefix: Option<String>) -> Self {
+        self.name_prefix = name_prefix;
+        self
+    }
+
+    pub fn with_mail(mut self, mail: Option<String>) -> Self {
+        self.mail = mail;
+        self
+    }
+
+    pub fn with_mail_domain(mut self, mail_domain: Option<String>) -> Self {
+        self.mail_domain = mail_domain;
+        self
+    }
+
+    pub fn with_sort(mut self, sort_by: UserSortField, descending: bool) -> Self {
+        self.sort_by = sort_by;
+        self.descending = descending;
+        self
+    }
+
+    pub fn with_text_query(mut self, text_query: Option<String>) -> Self {
+        self.text_query = text_query;
+        self
+    }
+
+    pub fn with_after_score(mut self, after_score: f64) -> Self {
+        self.after_score = Some(after_score);
+        self
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(QUERY_LIMIT)
+    }
+
+    // Terminos de la busqueda de texto completo en minusculas. Se descartan los
+    // caracteres que no son letras ni numeros, que cada motor usa como operadores
+    pub fn text_terms(&self) -> Vec<String> {
+        self.text_query
+            .iter()
+            .flat_map(|text_query| text_query.split_whitespace())
+            .map(|term| {
+                term.chars()
+                    .filter(|c| c.is_alphanumeric())
+                    .collect::<String>()
+                    .to_lowercase()
+            })
+            .filter(|term| !term.is_empty())
+            .collect()
+    }
+
+    pub fn is_ranked(&self) -> bool {
+        !self.text_terms().is_empty()
+    }
+
+    // Relevancia del usuario para la busqueda de texto completo: cada termino tiene
+    // que ser prefijo de alguna palabra del nombre, y suma mas si es la palabra
+    // entera. None si no matchea. Aproxima lo que hacen los indices de cada motor
+    pub fn text_score(&self, user: &UserModel) -> Option<f64> {
+        let name = user.name.to_lowercase();
+        let words: Vec<&str> = name
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        self.text_terms().iter().try_fold(0.0, |score, term| {
+            if words.iter().any(|word| word == term) {
+                Some(score + 1.0)
+            } else if words.iter().any(|word| word.starts_with(term.as_str())) {
+                Some(score + 0.5)
+            } else {
+                None
+            }
+        })
+    }
+
+    // Indica si el usuario cumple con los filtros de nombre y mail
+    pub fn matches_search(&self, user: &UserModel) -> bool {
+        let name = user.name.to_lowercase();
+        self.name_contains
+            .as_ref()
+            .is_none_or(|part| name.contains(&part.to_lowercase()))
+            && self
+                .name_prefix
+                .as_ref()
+                .is_none_or(|prefix| name.starts_with(&prefix.to_lowercase()))
+            && self.mail.as_ref().is_none_or(|mail| &user.mail == mail)
+            && self.mail_domain.as_ref().is_none_or(|domain| {
+                user.mail
+                    .rsplit_once('@')
+                    .is_some_and(|(_, user_domain)| user_domain == domain.to_lowercase())
+            })
+    }
+
+    // Orden en el que se devuelven los usuarios, el mismo que el ORDER BY de la query.
+    // Con busqueda de texto se espera que los usuarios tengan el score calculado
+    pub fn compare(&self, a: &UserModel, b: &UserModel) -> Ordering {
+        if self.is_ranked() {
+            return b
+                .score
+                .partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.id.cmp(&b.id));
+        }
+        let ordering =
+            (self.sort_by.value(a), a.id.as_str()).cmp(&(self.sort_by.value(b), b.id.as_str()));
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
         }
+    }
+
+    // Indica si el usuario va despues del cursor de paginacion en el orden pedido
+    pub fn is_after_cursor(&self, user: &UserModel) -> bool {
+        let Some(after_id) = &self.after_id else {
+            return true;
+        };
+        if let Some(after_score) = self.cursor_score() {
+            let score = user.score.unwrap_or_default();
+            return score < after_score || (score == after_score && user.id > *after_id);
+        }
+        let ordering = match self.cursor_key() {
+            Some(after_key) => (self.sort_by.value(user), user.id.as_str())
+                .cmp(&(after_key.as_str(), after_id.as_str())),
+            None => user.id.as_str().cmp(after_id.as_str()),
+        };
+        ordering
+            == if self.descending {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+    }
+
+    // Valor de la columna de orden del cursor, None cuando se pagina solo por id
+    fn cursor_key(&self) -> Option<&String> {
+        self.after_key
+            .as_ref()
+            .filter(|_| self.sort_by != UserSortField::Id && !self.is_ranked())
+    }
+
+    fn cursor_score(&self) -> Option<f64> {
+        self.after_score.filter(|_| self.is_ranked())
+    }
+
+    fn search_filters(&self) -> Vec<(&'static str, String)> {
+        vec![
+            self.name_contains
+                .as_ref()
+                .map(|part| ("LOWER(name) LIKE", format!("%{}%", escape_like(part)))),
+            self.name_prefix
+                .as_ref()
+                .map(|prefix| ("LOWER(name) LIKE", format!("{}%", escape_like(prefix)))),
+            // Los mails se guardan con el dominio en minusculas
+            self.mail_domain.as_ref().map(|domain| {
+                let domain = escape_like(&domain.to_lowercase());
+                ("mail LIKE", format!("%@{}", domain))
+            }),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    // Indica si el usuario cae dentro de los rangos de tiempo pedidos
+    pub fn matches_time_range(&self, user: &UserModel) -> bool {
+        self.created_after.is_none_or(|at| user.created_at >= at)
+            && self.created_before.is_none_or(|at| user.created_at < at)
+            && self.updated_after.is_none_or(|at| user.updated_at >= at)
+            && self.updated_before.is_none_or(|at| user.updated_at < at)
+    }
+
+    fn time_filters(&self) -> Vec<(&'static str, &'static str, DateTime<Utc>)> {
+        vec![
+            self.created_after.map(|at| ("created_at", ">=", at)),
+            self.created_before.map(|at| ("created_at", "<", at)),
+            self.updated_after.map(|at| ("updated_at", ">=", at)),
+            self.updated_before.map(|at| ("updated_at", "<", at)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    // Query completa sobre los usuarios: la tabla, o con busqueda de texto la
+    // subquery con el score de cada usuario que matchea, mas los filtros
+    pub(crate) fn build_query<'args, DB>(&self) -> QueryBuilder<'args, DB>
+    where
+        DB: Database + TimeComparison + FullTextSearch,
+        String: Encode<'args, DB> + Type<DB>,
+        i64: Encode<'args, DB> + Type<DB>,
+        f64: Encode<'args, DB> + Type<DB>,
+    {
+        let mut builder = QueryBuilder::new("SELECT * FROM ");
+        match self.text_terms() {
+            terms if terms.is_empty() => {
+                builder.push("users");
+            }
+            terms => DB::push_ranked_users(&mut builder, &terms),
+        }
+        self.push_query_filters(&mut builder);
+        builder
+    }
+
+    // Agrega al builder el WHERE, el ORDER BY (siempre desempatando por id, para
+    // tener un orden estable al paginar) y el LIMIT de la query
+    pub(crate) fn push_query_filters<'args, DB>(&self, builder: &mut QueryBuilder<'args, DB>)
+    where
+        DB: Database,
+        String: Encode<'args, DB> + Type<DB>,
+        i64: Encode<'args, DB> + Type<DB>,
+        f64: Encode<'args, DB> + Type<DB>,
+        DB: TimeComparison,
+    {
+        builder.push(" WHERE 1 = 1");
+        if !self.include_deleted {
+            builder.push(" AND deleted_at IS NULL");
+        }
+        let (operator, direction) = if self.descending {
+            ("<", "DESC")
+        } else {
+            (">", "ASC")
+        };
+        if let Some(after_id) = &self.after_id {
+            match (self.cursor_score(), self.cursor_key()) {
+                (Some(after_score), _) => {
+                    builder
+                        .push(" AND (score < ")
+                        .push_bind(after_score)
+                        .push(" OR (score = ")
+                        .push_bind(after_score)
+                        .push(" AND id > ")
+                        .push_bind(after_id.clone())
+                        .push("))");
+                }
+                (None, Some(after_key)) => {
+                    let column = self.sort_by.column();
+                    builder
+                        .push(format!(" AND ({} {} ", column, operator))
+                        .push_bind(after_key.clone())
+                        .push(format!(" OR ({} = ", column))
+                        .push_bind(after_key.clone())
+                        .push(format!(" AND id {} ", operator))
+                        .push_bind(after_id.clone())
+                        .push("))");
+                }
+                (None, None) => {
+                    builder
+                        .push(format!(" AND id {} ", operator))
+                        .push_bind(after_id.clone());
+                }
+            }
+        }
+        for (column, operator, at) in self.time_filters() {
+            builder.push(" AND ");
+            DB::push_time_comparison(builder, column, operator, at);
+        }
+        if let Some(mail) = &self.mail {
+            builder.push(" AND mail = ").push_bind(mail.clone());
+        }
+        for (condition, pattern) in self.search_filters() {
+            builder
+                .push(format!(" AND {} ", condition))
+                .push_bind(pattern)
+                .push(format!(" ESCAPE '{}'", LIKE_ESCAPE));
+        }
+        match self.sort_by {
+            _ if self.is_ranked() => builder.push(" ORDER BY score DESC, id ASC"),
+            UserSortField::Id => builder.push(format!(" ORDER BY id {}", direction)),
+            sort_by => builder.push(format!(
+                " ORDER BY {} {}, id {}",
+                sort_by.column(),
+                direction,
+                direction
+            )),
+        };
+        builder.push(" LIMIT ").push_bind(i64::from(self.limit()));
+    }
+}
+
+// Se usa ! como caracter de escape del LIKE porque la barra invertida se
+// interpreta distinto en los literales de MySQL y de Postgres
+const LIKE_ESCAPE: char = '!';
+
+// Patron en minusculas con los comodines del LIKE escapados, para buscar el texto literal
+fn escape_like(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .fold(String::new(), |mut pattern, c| {
+            if matches!(c, '%' | '_' | LIKE_ESCAPE) {
+                pattern.push(LIKE_ESCAPE);
+            }
+            pattern.push(c);
+            pattern
+        })
+}
+
+// Evento de auditoria a registrar en la misma transaccion que la operacion
+#[derive(Debug, Clone)]
+pub struct AuditEventScheme {
+    pub operation: AuditOperation,
+    pub user_id: Option<String>,
+    pub before_value: Option<String>,
+    pub after_value: Option<String>,
+    pub caller: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AuditEventScheme {
+    pub fn new(
+        operation: AuditOperation,
+        user_id: Option<&str>,
+        before: Option<&UserModel>,
+        after: Option<&UserModel>,
+        caller: &str,
+    ) -> Result<Self, ErrorKinsper> {
+        let to_json = |user: Option<&UserModel>| {
+            user.map(serde_json::to_string).transpose().map_err(|err| {
+                ErrorKinsper::InternalServer(format!("Error serializing audit event: {}", err))
+            })
+        };
+
+        Ok(AuditEventScheme {
+            operation,
+            user_id: user_id.map(str::to_string),
+            before_value: to_json(before)?,
+            after_value: to_json(after)?,
+            caller: caller.to_string(),
+            created_at: Utc::now(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GetAuditEventsScheme {
+    pub limit: Option<u32>,
+    // Keyset pagination: solo se devuelven eventos con id mayor a este
+    pub after_id: Option<i64>,
+    pub user_id: Option<String>,
+    pub operation: Option<AuditOperation>,
+    // El limite inferior es inclusivo y el superior exclusivo
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl GetAuditEventsScheme {
+    pub fn new() -> Self {
+        GetAuditEventsScheme {
+            limit: None,
+            after_id: None,
+            user_id: None,
+            operation: None,
+            created_after: None,
+            created_before: None,
+        }
+    }
+
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn with_after_id(mut self, after_id: i64) -> Self {
+        self.after_id = Some(after_id);
+        self
+    }
+
+    pub fn with_user_id(mut self, user_id: Option<String>) -> Self {
+        self.user_id = user_id;
+        self
+    }
+
+    pub fn with_operation(mut self, operation: Option<AuditOperation>) -> Self {
+        self.operation = operation;
+        self
+    }
+
+    pub fn with_created_after(mut self, created_after: Option<DateTime<Utc>>) -> Self {
+        self.created_after = created_after;
+        self
+    }
+
+    pub fn with_created_before(mut self, created_before: Option<DateTime<Utc>>) -> Self {
+        self.created_before = created_before;
+        self
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(QUERY_LIMIT)
+    }
+
+    pub fn matches(&self, event: &AuditEventModel) -> bool {
+        self.after_id.is_none_or(|after_id| event.id > after_id)
+            && self
+                .user_id
+                .as_ref()
+                .is_none_or(|user_id| event.user_id.as_ref() == Some(user_id))
+            && self
+                .operation
+                .is_none_or(|operation| event.operation == operation.as_str())
+            && self.created_after.is_none_or(|at| event.created_at >= at)
+            && self.created_before.is_none_or(|at| event.created_at < at)
+    }
+
+    // Agrega al builder el WHERE, el ORDER BY por id (orden cronologico) y el LIMIT
+    pub(crate) fn push_query_filters<'args, DB>(&self, builder: &mut QueryBuilder<'args, DB>)
+    where
+        DB: Database,
+        String: Encode<'args, DB> + Type<DB>,
+        i64: Encode<'args, DB> + Type<DB>,
+        DB: TimeComparison,
+    {
+        builder.push(" WHERE 1 = 1");
+        if let Some(after_id) = self.after_id {
+            builder.push(" AND id > ").push_bind(after_id);
+        }
+        if let Some(user_id) = &self.user_id {
+            builder.push(" AND user_id = ").push_bind(user_id.clone());
+        }
+        if let Some(operation) = self.operation {
+            builder
+                .push(" AND operation = ")
+                .push_bind(operation.as_str().to_string());
+        }
+        if let Some(at) = self.created_after {
+            builder.push(" AND ");
+            DB::push_time_comparison(builder, "created_at", ">=", at);
+        }
+        if let Some(at) = self.created_before {
+            builder.push(" AND ");
+            DB::push_time_comparison(builder, "created_at", "<", at);
+        }
+        builder
+            .push(" ORDER BY id LIMIT ")
+            .push_bind(i64::from(self.limit()));
+    }
+}
+
+// Como cada motor compara una columna de fecha contra un parametro bindeado
+pub(crate) trait TimeComparison: Database + Sized {
+    fn push_time_comparison(
+        builder: &mut QueryBuilder<'_, Self>,
+        column: &str,
+        operator: &str,
+        at: DateTime<Utc>,
+    );
+}
+
+impl TimeComparison for MySql {
+    fn push_time_comparison(
+        builder: &mut QueryBuilder<'_, Self>,
+        column: &str,
+        operator: &str,
+        at: DateTime<Utc>,
+    ) {
+        builder
+            .push(format!("{} {} ", column, operator))
+            .push_bind(at);
+    }
+}
+
+impl TimeComparison for Postgres {
+    fn push_time_comparison(
+        builder: &mut QueryBuilder<'_, Self>,
+        column: &str,
+        operator: &str,
+        at: DateTime<Utc>,
+    ) {
+        builder
+            .push(format!("{} {} ", column, operator))
+            .push_bind(at);
+    }
+}
+
+// Decimales con que se redondea el score. Los motores lo recalculan como float en
+// cada query, redondeado es el mismo valor en todas las paginas y en el page token,
+// asi los empates se desempatan siempre por id
+pub const SCORE_PRECISION: u32 = 6;
+
+// Como cada motor arma, con su indice de texto completo, la subquery de los
+// usuarios cuyo nombre matchea todos los terminos (como prefijos) junto con su
+// relevancia en la columna score (redondeada a SCORE_PRECISION), mayor es mas
+// relevante
+pub(crate) trait FullTextSearch: Database + Sized {
+    fn push_ranked_users(builder: &mut QueryBuilder<'_, Self>, terms: &[String]);
+}
+
+// Indice FULLTEXT en modo booleano: +term* exige cada termino como prefijo
+impl FullTextSearch for MySql {
+    fn push_ranked_users(builder: &mut QueryBuilder<'_, Self>, terms: &[String]) {
+        let against = terms
+            .iter()
+            .map(|term| format!("+{}*", term))
+            .collect::<Vec<_>>()
+            .join(" ");
+        builder
+            .push("(SELECT users.*, ROUND(MATCH(name) AGAINST (")
+            .push_bind(against.clone())
+            .push(format!(
+                " IN BOOLEAN MODE), {}) AS score FROM users WHERE MATCH(name) AGAINST (",
+                SCORE_PRECISION
+            ))
+            .push_bind(against)
+            .push(" IN BOOLEAN MODE)) AS ranked");
+    }
+}
+
+// Indice GIN sobre to_tsvector('simple', name), term:* busca el prefijo
+impl FullTextSearch for Postgres {
+    fn push_ranked_users(builder: &mut QueryBuilder<'_, Self>, terms: &[String]) {
+        let tsquery = terms
+            .iter()
+            .map(|term| format!("{}:*", term))
+            .collect::<Vec<_>>()
+            .join(" & ");
+        builder
+            .push("(SELECT users.*, ROUND(ts_rank(to_tsvector('simple', name), to_tsquery('simple', ")
+            .push_bind(tsquery.clone())
+            .push(format!(
+                "))::numeric, {})::float8 AS score FROM users WHERE to_tsvector('simple', name) @@ to_tsquery('simple', ",
+                SCORE_PRECISION
+            ))
+            .push_bind(tsquery)
+            .push(")) AS ranked");
+    }
+}
+
+// Tabla FTS5 users_fts. bm25 devuelve valores mas chicos para los mas relevantes,
+// por eso se invierte el signo
+impl FullTextSearch for Sqlite {
+    fn push_ranked_users(builder: &mut QueryBuilder<'_, Self>, terms: &[String]) {
+        let matches = terms
+            .iter()
+            .map(|term| format!("\"{}\"*", term))
+            .collect::<Vec<_>>()
+            .join(" ");
+        builder
+            .push(format!(
+                "(SELECT users.*, ROUND(-bm25(users_fts), {}) AS score FROM users_fts",
+                SCORE_PRECISION
+            ))
+            .push(" JOIN users_fts_ids ON users_fts_ids.fts_rowid = users_fts.rowid")
+            .push(" JOIN users ON users.id = users_fts_ids.id WHERE users_fts MATCH ")
+            .push_bind(matches)
+            .push(") AS ranked");
+    }
+}
 
-        Ok(updates.join(", "))
+// SQLite guarda las fechas como texto y no siempre con el mismo formato, asi que
+// se comparan con julianday() como en purge_deleted_users
+impl TimeComparison for Sqlite {
+    fn push_time_comparison(
+        builder: &mut QueryBuilder<'_, Self>,
+        column: &str,
+        operator: &str,
+        at: DateTime<Utc>,
+    ) {
+        builder
+            .push(format!("julianday({}) {} julianday(", column, operator))
+            .push_bind(at)
+            .push(")");
     }
 }
@@ -0,0 +1,993 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use sqlx::{
+    database::HasArguments,
+    migrate::{Migrate, Migrator},
+    mysql::MySqlQueryResult,
+    postgres::PgQueryResult,
+    query::{Query, QueryAs},
+    sqlite::SqliteQueryResult,
+    Database, Encode, Executor, FromRow, IntoArguments, MySql, Pool, Postgres, QueryBuilder,
+    Sqlite, Transaction, Type,
+};
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    data::{debug_thread, QUERY_STREAM_BUFFER},
+    errors::ErrorKinsper,
+    normalize_mail, MailLocalPartPolicy,
+};
+
+use super::{
+    migrations::{
+        self, MigrationStatus, SchemaMigrations, MYSQL_MIGRATOR, POSTGRES_MIGRATOR, SQLITE_MIGRATOR,
+    },
+    model::{AuditEventModel, AuditOperation, UserModel},
+    repository::{version_mismatch_or, UserRepository, UserStream},
+    scheme::{
+        AuditEventScheme, CreateUserScheme, FullTextSearch, GetAuditEventsScheme, GetUsersScheme,
+        TimeComparison, UpdateUserSchema,
+    },
+};
+
+// Backend SQL de usuarios. Las transacciones, la auditoria y el control de version
+// son los mismos en todos los motores, lo que cambia de cada uno lo define SqlBackend.
+// Cada motor tiene su alias (Database, PostgresDatabase, SqliteDatabase) con su connect
+type Arguments<'q, DB> = <DB as HasArguments<'q>>::Arguments;
+
+pub struct SqlDatabase<DB: Database> {
+    // https://docs.rs/sqlx/latest/sqlx/struct.Pool.html#why-use-a-pool
+    pub pool: Arc<Pool<DB>>,
+}
+
+// Lo que cambia entre motores ademas de los placeholders, que los resuelve QueryBuilder
+pub(crate) trait SqlBackend: Database + TimeComparison + FullTextSearch {
+    fn migrator() -> &'static Migrator;
+
+    fn rows_affected(result: &Self::QueryResult) -> u64;
+
+    // Con el motor generico QueryBuilder::build deja el builder prestado por toda
+    // su vida, cada motor acorta el prestamo al de la query
+    fn build<'q>(query: &'q mut QueryBuilder<'_, Self>) -> Query<'q, Self, Arguments<'q, Self>>;
+
+    fn build_query_as<'q, T>(
+        query: &'q mut QueryBuilder<'_, Self>,
+    ) -> QueryAs<'q, Self, T, Arguments<'q, Self>>
+    where
+        T: for<'r> FromRow<'r, Self::Row>;
+
+    // Se agrega a los SELECT que leen filas que la transaccion despues modifica
+    const LOCK_ROWS: &'static str = " FOR UPDATE";
+
+    // Sentencias que copian users a la tabla snapshot_table en reset_table. La
+    // primera se ejecuta antes de la transaccion, para los motores donde el DDL hace
+    // commit implicito, y la segunda dentro
+    fn snapshot_statements(snapshot_table: &str) -> (Option<String>, String) {
+        (
+            None,
+            format!("CREATE TABLE {} AS SELECT * FROM users", snapshot_table),
+        )
+    }
+}
+
+impl SqlBackend for MySql {
+    fn migrator() -> &'static Migrator {
+        &MYSQL_MIGRATOR
+    }
+
+    fn rows_affected(result: &MySqlQueryResult) -> u64 {
+        result.rows_affected()
+    }
+
+    fn build<'q>(query: &'q mut QueryBuilder<'_, Self>) -> Query<'q, Self, Arguments<'q, Self>> {
+        query.build()
+    }
+
+    fn build_query_as<'q, T>(
+        query: &'q mut QueryBuilder<'_, Self>,
+    ) -> QueryAs<'q, Self, T, Arguments<'q, Self>>
+    where
+        T: for<'r> FromRow<'r, Self::Row>,
+    {
+        query.build_query_as()
+    }
+
+    // En MySQL el CREATE TABLE hace commit implicito, por eso se crea antes de la
+    // transaccion y la copia se hace adentro
+    fn snapshot_statements(snapshot_table: &str) -> (Option<String>, String) {
+        (
+            Some(format!("CREATE TABLE {} LIKE users", snapshot_table)),
+            format!("INSERT INTO {} SELECT * FROM users", snapshot_table),
+        )
+    }
+}
+
+impl SqlBackend for Postgres {
+    fn migrator() -> &'static Migrator {
+        &POSTGRES_MIGRATOR
+    }
+
+    fn rows_affected(result: &PgQueryResult) -> u64 {
+        result.rows_affected()
+    }
+
+    fn build<'q>(query: &'q mut QueryBuilder<'_, Self>) -> Query<'q, Self, Arguments<'q, Self>> {
+        query.build()
+    }
+
+    fn build_query_as<'q, T>(
+        query: &'q mut QueryBuilder<'_, Self>,
+    ) -> QueryAs<'q, Self, T, Arguments<'q, Self>>
+    where
+        T: for<'r> FromRow<'r, Self::Row>,
+    {
+        query.build_query_as()
+    }
+}
+
+// SQLite bloquea la base entera al escribir, no tiene FOR UPDATE
+impl SqlBackend for Sqlite {
+    const LOCK_ROWS: &'static str = "";
+
+    fn migrator() -> &'static Migrator {
+        &SQLITE_MIGRATOR
+    }
+
+    fn rows_affected(result: &SqliteQueryResult) -> u64 {
+        result.rows_affected()
+    }
+
+    fn build<'q>(query: &'q mut QueryBuilder<'_, Self>) -> Query<'q, Self, Arguments<'q, Self>> {
+        query.build()
+    }
+
+    fn build_query_as<'q, T>(
+        query: &'q mut QueryBuilder<'_, Self>,
+    ) -> QueryAs<'q, Self, T, Arguments<'q, Self>>
+    where
+        T: for<'r> FromRow<'r, Self::Row>,
+    {
+        query.build_query_as()
+    }
+}
+
+#[async_trait]
+impl<DB> SchemaMigrations for SqlDatabase<DB>
+where
+    DB: SqlBackend,
+    DB::Connection: Migrate,
+{
+    async fn migrate_up(&self) -> Result<(), ErrorKinsper> {
+        debug_thread();
+
+        let mut conn = self.pool.acquire().await?;
+        migrations::run(DB::migrator(), &mut *conn).await
+    }
+
+    async fn migrate_down(&self) -> Result<Option<i64>, ErrorKinsper> {
+        debug_thread();
+
+        let mut conn = self.pool.acquire().await?;
+        migrations::undo_last(DB::migrator(), &mut *conn).await
+    }
+
+    async fn migrate_reset(&self) -> Result<(), ErrorKinsper> {
+        debug_thread();
+
+        let mut conn = self.pool.acquire().await?;
+        migrations::undo(DB::migrator(), &mut *conn, 0).await?;
+        Ok(())
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, ErrorKinsper> {
+        debug_thread();
+
+        let mut conn = self.pool.acquire().await?;
+        migrations::status(DB::migrator(), &mut *conn).await
+    }
+}
+
+// Lee el usuario dentro de la transaccion, este borrado o no, bloqueando la fila
+// hasta el commit para auditar su estado
+async fn fetch_user<DB>(
+    tx: &mut Transaction<'_, DB>,
+    id: &str,
+) -> Result<Option<UserModel>, ErrorKinsper>
+where
+    DB: SqlBackend,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'r> UserModel: FromRow<'r, DB::Row>,
+    for<'q> String: Encode<'q, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new("SELECT * FROM users WHERE id = ");
+    query.push_bind(id.to_string()).push(DB::LOCK_ROWS);
+    let user = DB::build_query_as::<UserModel>(&mut query)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    Ok(user)
+}
+
+async fn record_audit<DB>(
+    tx: &mut Transaction<'_, DB>,
+    event: &AuditEventScheme,
+) -> Result<(), ErrorKinsper>
+where
+    DB: SqlBackend,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> String: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<String>: Encode<'q, DB> + Type<DB>,
+    for<'q> DateTime<Utc>: Encode<'q, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new(
+        "INSERT INTO user_audit (operation, user_id, before_value, after_value, caller, created_at) ",
+    );
+    query.push_values([event], |mut row, event| {
+        row.push_bind(event.operation.as_str().to_string())
+            .push_bind(event.user_id.clone())
+            .push_bind(event.before_value.clone())
+            .push_bind(event.after_value.clone())
+            .push_bind(event.caller.clone())
+            .push_bind(event.created_at);
+    });
+    DB::build(&mut query).execute(&mut **tx).await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl<DB> UserRepository for SqlDatabase<DB>
+where
+    DB: SqlBackend,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'r> UserModel: FromRow<'r, DB::Row>,
+    for<'r> AuditEventModel: FromRow<'r, DB::Row>,
+    for<'q> String: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<String>: Encode<'q, DB> + Type<DB>,
+    for<'q> DateTime<Utc>: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<i64>: Encode<'q, DB> + Type<DB>,
+    for<'r> (Option<i64>,): FromRow<'r, DB::Row>,
+    for<'q> i64: Encode<'q, DB> + Type<DB>,
+    for<'q> f64: Encode<'q, DB> + Type<DB>,
+{
+    async fn reset_table(
+        &self,
+        snapshot_table: Option<&str>,
+        caller: &str,
+    ) -> Result<(), ErrorKinsper> {
+        debug_thread();
+
+        // El nombre lo genera el servidor, ver snapshot_table_name
+        let snapshot = snapshot_table.map(DB::snapshot_statements);
+        if let Some((Some(create), _)) = &snapshot {
+            sqlx::query::<DB>(create).execute(&*self.pool).await?;
+        }
+
+        let reset = async {
+            let mut tx = self.pool.begin().await?;
+            if let Some((_, copy)) = &snapshot {
+                sqlx::query::<DB>(copy).execute(&mut *tx).await?;
+            }
+            sqlx::query::<DB>("DELETE FROM users")
+                .execute(&mut *tx)
+                .await?;
+            let event = AuditEventScheme::new(AuditOperation::Reset, None, None, None, caller)?;
+            record_audit(&mut tx, &event).await?;
+            tx.commit().await?;
+            Ok::<(), ErrorKinsper>(())
+        }
+        .await;
+
+        // Si la tabla se creo fuera de la transaccion, el rollback no la borra y
+        // quedaria una copia incompleta
+        if let (Err(_), Some((Some(_), _)), Some(snapshot_table)) =
+            (&reset, &snapshot, snapshot_table)
+        {
+            let drop = format!("DROP TABLE IF EXISTS {}", snapshot_table);
+            if let Err(err) = sqlx::query::<DB>(&drop).execute(&*self.pool).await {
+                log::error!(
+                    "Couldn't drop the incomplete snapshot table {}: {:?}",
+                    snapshot_table,
+                    err
+                );
+            }
+        }
+
+        reset
+    }
+
+    async fn add_user(
+        &self,
+        user: &CreateUserScheme,
+        caller: &str,
+    ) -> Result<UserModel, ErrorKinsper> {
+        debug_thread();
+
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        let mut query =
+            QueryBuilder::new("INSERT INTO users (id, name, mail, created_at, updated_at) ");
+        query.push_values([user], |mut row, user| {
+            row.push_bind(user.id.clone())
+                .push_bind(user.name.clone())
+                .push_bind(user.mail.clone())
+                .push_bind(now)
+                .push_bind(now);
+        });
+        let result = DB::build(&mut query).execute(&mut *tx).await?;
+
+        if DB::rows_affected(&result) == 0 {
+            return Err(ErrorKinsper::AlreadyExists(
+                "User already exists.".to_string(),
+            ));
+        }
+
+        let created = fetch_user(&mut tx, &user.id)
+            .await?
+            .ok_or_else(|| ErrorKinsper::InternalServer("Inserted user not found.".to_string()))?;
+        let event = AuditEventScheme::new(
+            AuditOperation::Create,
+            Some(&user.id),
+            None,
+            Some(&created),
+            caller,
+        )?;
+        record_audit(&mut tx, &event).await?;
+        tx.commit().await?;
+
+        Ok(created)
+    }
+
+    async fn get_users(&self, query: &GetUsersScheme) -> Result<Vec<UserModel>, ErrorKinsper> {
+        debug_thread();
+
+        let mut builder = query.build_query();
+
+        let result = DB::build_query_as::<UserModel>(&mut builder)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        if result.is_empty() {
+            Err(ErrorKinsper::NotFound("No users found.".to_string()))
+        } else {
+            Ok(result)
+        }
+    }
+
+    // Los usuarios se leen del cursor de la query fila a fila y se pasan por un
+    // canal acotado, asi la query avanza al ritmo del consumidor y se corta
+    // cuando el stream se descarta
+    fn stream_users(&self, query: &GetUsersScheme) -> UserStream {
+        debug_thread();
+
+        let pool = self.pool.clone();
+        let query = query.clone();
+        let (tx, rx) = mpsc::channel(QUERY_STREAM_BUFFER);
+
+        tokio::spawn(async move {
+            let mut builder = query.build_query();
+
+            let mut rows = DB::build_query_as::<UserModel>(&mut builder).fetch(&*pool);
+            while let Some(row) = rows.next().await {
+                if tx.send(row.map_err(ErrorKinsper::from)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        ReceiverStream::new(rx).boxed()
+    }
+
+    async fn get_user_by_id(&self, id: &str) -> Result<UserModel, ErrorKinsper> {
+        debug_thread();
+
+        let mut query = QueryBuilder::new("SELECT * FROM users WHERE id = ");
+        query
+            .push_bind(id.to_string())
+            .push(" AND deleted_at IS NULL");
+        let result = DB::build_query_as::<UserModel>(&mut query)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn get_user_by_mail(&self, mail: &str) -> Result<UserModel, ErrorKinsper> {
+        debug_thread();
+
+        let mut query = QueryBuilder::new("SELECT * FROM users WHERE mail = ");
+        query
+            .push_bind(mail.to_string())
+            .push(" AND deleted_at IS NULL");
+        let result = DB::build_query_as::<UserModel>(&mut query)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn update_user(
+        &self,
+        id: &str,
+        user: &UpdateUserSchema,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        debug_thread();
+
+        let mut tx = self.pool.begin().await?;
+        let before = fetch_user(&mut tx, id)
+            .await?
+            .filter(|user| user.deleted_at.is_none());
+
+        let mut query = QueryBuilder::new("UPDATE users");
+        user.push_query_set(&mut query, Utc::now());
+        query
+            .push(" WHERE id = ")
+            .push_bind(id.to_string())
+            .push(" AND deleted_at IS NULL");
+        user.push_query_version(&mut query);
+
+        let result = DB::build(&mut query).execute(&mut *tx).await?;
+
+        if DB::rows_affected(&result) == 0 {
+            return Err(version_mismatch_or(
+                before.as_ref(),
+                user.expected_version,
+                ErrorKinsper::NotFound("User not found.".to_string()),
+            ));
+        }
+
+        let after = fetch_user(&mut tx, user.id.as_deref().unwrap_or(id)).await?;
+        let event = AuditEventScheme::new(
+            AuditOperation::Update,
+            Some(id),
+            before.as_ref(),
+            after.as_ref(),
+            caller,
+        )?;
+        record_audit(&mut tx, &event).await?;
+        tx.commit().await?;
+
+        Ok(DB::rows_affected(&result))
+    }
+
+    async fn delete_user(
+        &self,
+        id: &str,
+        expected_version: Option<i64>,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        debug_thread();
+
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        let before = fetch_user(&mut tx, id)
+            .await?
+            .filter(|user| user.deleted_at.is_none());
+
+        let mut query = QueryBuilder::new("UPDATE users SET deleted_at = ");
+        query
+            .push_bind(now)
+            .push(", updated_at = ")
+            .push_bind(now)
+            .push(", version = version + 1 WHERE id = ")
+            .push_bind(id.to_string())
+            .push(" AND deleted_at IS NULL AND version = COALESCE(")
+            .push_bind(expected_version)
+            .push(", version)");
+        let result = DB::build(&mut query).execute(&mut *tx).await?;
+
+        if DB::rows_affected(&result) == 0 {
+            return Err(version_mismatch_or(
+                before.as_ref(),
+                expected_version,
+                ErrorKinsper::NotFound("User not found.".to_string()),
+            ));
+        }
+
+        let after = fetch_user(&mut tx, id).await?;
+        let event = AuditEventScheme::new(
+            AuditOperation::Delete,
+            Some(id),
+            before.as_ref(),
+            after.as_ref(),
+            caller,
+        )?;
+        record_audit(&mut tx, &event).await?;
+        tx.commit().await?;
+
+        Ok(DB::rows_affected(&result))
+    }
+
+    async fn restore_user(&self, id: &str, caller: &str) -> Result<u64, ErrorKinsper> {
+        debug_thread();
+
+        let mut tx = self.pool.begin().await?;
+        let before = fetch_user(&mut tx, id).await?;
+
+        let mut query = QueryBuilder::new("UPDATE users SET deleted_at = NULL, updated_at = ");
+        query
+            .push_bind(Utc::now())
+            .push(", version = version + 1 WHERE id = ")
+            .push_bind(id.to_string())
+            .push(" AND deleted_at IS NOT NULL");
+        let result = DB::build(&mut query).execute(&mut *tx).await?;
+
+        if DB::rows_affected(&result) == 0 {
+            return Err(ErrorKinsper::NotFound(
+                "Deleted user not found.".to_string(),
+            ));
+        }
+
+        let after = fetch_user(&mut tx, id).await?;
+        let event = AuditEventScheme::new(
+            AuditOperation::Restore,
+            Some(id),
+            before.as_ref(),
+            after.as_ref(),
+            caller,
+        )?;
+        record_audit(&mut tx, &event).await?;
+        tx.commit().await?;
+
+        Ok(DB::rows_affected(&result))
+    }
+
+    // Se registra un evento por cada usuario purgado, con su ultimo estado
+    async fn purge_deleted_users(
+        &self,
+        deleted_before: DateTime<Utc>,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        debug_thread();
+
+        let mut tx = self.pool.begin().await?;
+        let mut query = QueryBuilder::new("SELECT * FROM users WHERE deleted_at IS NOT NULL AND ");
+        DB::push_time_comparison(&mut query, "deleted_at", "<", deleted_before);
+        query.push(DB::LOCK_ROWS);
+        let purged = DB::build_query_as::<UserModel>(&mut query)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        let mut query = QueryBuilder::new("DELETE FROM users WHERE deleted_at IS NOT NULL AND ");
+        DB::push_time_comparison(&mut query, "deleted_at", "<", deleted_before);
+        let result = DB::build(&mut query).execute(&mut *tx).await?;
+
+        for user in &purged {
+            let event = AuditEventScheme::new(
+                AuditOperation::Purge,
+                Some(&user.id),
+                Some(user),
+                None,
+                caller,
+            )?;
+            record_audit(&mut tx, &event).await?;
+        }
+        tx.commit().await?;
+
+        Ok(DB::rows_affected(&result))
+    }
+
+    async fn normalize_mails(
+        &self,
+        policy: MailLocalPartPolicy,
+        caller: &str,
+    ) -> Result<u64, ErrorKinsper> {
+        debug_thread();
+
+        let mut tx = self.pool.begin().await?;
+        let mut query = QueryBuilder::new("SELECT * FROM users");
+        query.push(DB::LOCK_ROWS);
+        let users = DB::build_query_as::<UserModel>(&mut query)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        let mut normalized = 0;
+        for before in users {
+            let mail = normalize_mail(&before.mail, policy);
+            if mail == before.mail {
+                continue;
+            }
+            let mut query = QueryBuilder::new("UPDATE users");
+            UpdateUserSchema::new()
+                .with_mail(mail)
+                .push_query_set(&mut query, Utc::now());
+            query.push(" WHERE id = ").push_bind(before.id.clone());
+            DB::build(&mut query).execute(&mut *tx).await?;
+
+            let after = fetch_user(&mut tx, &before.id).await?;
+            let event = AuditEventScheme::new(
+                AuditOperation::Update,
+                Some(&before.id),
+                Some(&before),
+                after.as_ref(),
+                caller,
+            )?;
+            record_audit(&mut tx, &event).await?;
+            normalized += 1;
+        }
+        tx.commit().await?;
+
+        Ok(normalized)
+    }
+
+    async fn list_audit_events(
+        &self,
+        query: &GetAuditEventsScheme,
+    ) -> Result<Vec<AuditEventModel>, ErrorKinsper> {
+        debug_thread();
+
+        let mut builder = QueryBuilder::new("SELECT * FROM user_audit");
+        query.push_query_filters(&mut builder);
+
+        let result = DB::build_query_as::<AuditEventModel>(&mut builder)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn last_audit_event_id(&self) -> Result<Option<i64>, ErrorKinsper> {
+        debug_thread();
+
+        let (result,) = sqlx::query_as::<DB, (Option<i64>,)>("SELECT MAX(id) FROM user_audit")
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(result)
+    }
+}
+
+// Escenarios de soft delete, timestamps, versionado, auditoria, restore y purga que
+// corren las suites de cada motor y la del backend en memoria. La tabla puede ser compartida con otros tests
+// (MySQL y Postgres), asi que cada escenario trabaja solo sobre los ids que recibe,
+// filtra las consultas por ellos y purga sus usuarios al terminar
+#[cfg(test)]
+pub(crate) mod sql_tests {
+    use chrono::{Duration, Utc};
+
+    use crate::data::{
+        model::AuditOperation,
+        repository::UserRepository,
+        scheme::{CreateUserScheme, GetAuditEventsScheme, GetUsersScheme, UpdateUserSchema},
+    };
+    use crate::errors::ErrorKinsper;
+
+    fn new_user(id: &str) -> CreateUserScheme {
+        CreateUserScheme {
+            id: id.to_string(),
+            name: "Fede".to_string(),
+            mail: format!("fede{}@gmail.com", id),
+        }
+    }
+
+    fn by_mail(id: &str) -> GetUsersScheme {
+        GetUsersScheme::new().with_mail(Some(new_user(id).mail))
+    }
+
+    async fn purge(db_context: &impl UserRepository, id: &str) {
+        if db_context.get_user_by_id(id).await.is_ok() {
+            db_context.delete_user(id, None, "test").await.unwrap();
+        }
+        db_context
+            .purge_deleted_users(Utc::now() + Duration::seconds(1), "test")
+            .await
+            .unwrap();
+    }
+
+    pub(crate) async fn when_delete_user_then_hidden_until_restored(
+        db_context: &impl UserRepository,
+        id: &str,
+    ) {
+        db_context.add_user(&new_user(id), "test").await.unwrap();
+
+        db_context.delete_user(id, None, "test").await.unwrap();
+        assert!(matches!(
+            db_context.get_user_by_id(id).await,
+            Err(ErrorKinsper::NotFound(_))
+        ));
+        assert!(db_context.get_users(&by_mail(id)).await.is_err());
+        let deleted = db_context
+            .get_users(&by_mail(id).with_include_deleted(true))
+            .await
+            .unwrap();
+        assert!(deleted[0].deleted_at.is_some());
+
+        db_context.restore_user(id, "test").await.unwrap();
+        let restored = db_context.get_user_by_id(id).await.unwrap();
+        assert!(restored.deleted_at.is_none());
+        assert!(db_context.restore_user(id, "test").await.is_err());
+
+        purge(db_context, id).await;
+    }
+
+    pub(crate) async fn when_purge_deleted_users_then_only_removes_rows_deleted_before_retention(
+        db_context: &impl UserRepository,
+        deleted_id: &str,
+        kept_id: &str,
+    ) {
+        db_context
+            .add_user(&new_user(deleted_id), "test")
+            .await
+            .unwrap();
+        db_context
+            .add_user(&new_user(kept_id), "test")
+            .await
+            .unwrap();
+        db_context
+            .delete_user(deleted_id, None, "test")
+            .await
+            .unwrap();
+        let last_event_id = db_context.last_audit_event_id().await.unwrap().unwrap();
+
+        db_context
+            .purge_deleted_users(Utc::now() - Duration::days(1), "test")
+            .await
+            .unwrap();
+        let deleted = db_context
+            .get_users(&by_mail(deleted_id).with_include_deleted(true))
+            .await
+            .unwrap();
+        assert_eq!(deleted.len(), 1);
+
+        let purged = db_context
+            .purge_deleted_users(Utc::now() + Duration::seconds(1), "purger")
+            .await
+            .unwrap();
+        assert!(purged >= 1);
+        assert!(db_context.restore_user(deleted_id, "test").await.is_err());
+        assert!(db_context.get_user_by_id(kept_id).await.is_ok());
+
+        // La purga deja en la auditoria el ultimo estado del usuario
+        let events = db_context
+            .list_audit_events(
+                &GetAuditEventsScheme::new()
+                    .with_after_id(last_event_id)
+                    .with_user_id(Some(deleted_id.to_string())),
+            )
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].operation, AuditOperation::Purge.as_str());
+        assert_eq!(events[0].caller, "purger");
+        assert!(events[0].before_value.is_some());
+        assert!(events[0].after_value.is_none());
+
+        purge(db_context, kept_id).await;
+    }
+
+    pub(crate) async fn when_get_users_given_time_ranges_then_filters_by_created_and_updated_at(
+        db_context: &impl UserRepository,
+        old_id: &str,
+        new_id: &str,
+    ) {
+        // SQLite compara con precision de milisegundos
+        let tick = || tokio::time::sleep(std::time::Duration::from_millis(5));
+        db_context
+            .add_user(&new_user(old_id), "test")
+            .await
+            .unwrap();
+        let created = db_context.get_user_by_id(old_id).await.unwrap();
+        assert_eq!(created.created_at, created.updated_at);
+        tick().await;
+        let checkpoint = Utc::now();
+        tick().await;
+        db_context
+            .add_user(&new_user(new_id), "test")
+            .await
+            .unwrap();
+        let updated_user = UpdateUserSchema::new()
+            .with_name("Jorge".to_string())
+            .finalize()
+            .unwrap();
+        db_context
+            .update_user(old_id, &updated_user, "test")
+            .await
+            .unwrap();
+
+        let updated = db_context.get_user_by_id(old_id).await.unwrap();
+        assert_eq!(updated.created_at, created.created_at);
+        assert!(updated.updated_at > updated.created_at);
+
+        let after = GetUsersScheme::new().with_created_after(Some(checkpoint));
+        assert!(db_context
+            .get_users(&after.clone().with_mail(Some(new_user(new_id).mail)))
+            .await
+            .is_ok());
+        assert!(matches!(
+            db_context
+                .get_users(&after.with_mail(Some(new_user(old_id).mail)))
+                .await,
+            Err(ErrorKinsper::NotFound(_))
+        ));
+        let updated = db_context
+            .get_users(
+                &by_mail(old_id)
+                    .with_created_before(Some(checkpoint))
+                    .with_updated_after(Some(checkpoint)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated[0].id, old_id);
+        let result = db_context
+            .get_users(&by_mail(old_id).with_updated_before(Some(checkpoint)))
+            .await;
+        assert!(matches!(result, Err(ErrorKinsper::NotFound(_))));
+
+        purge(db_context, old_id).await;
+        purge(db_context, new_id).await;
+    }
+
+    pub(crate) async fn when_write_given_stale_expected_version_then_returns_version_mismatch(
+        db_context: &impl UserRepository,
+        id: &str,
+        missing_id: &str,
+    ) {
+        db_context.add_user(&new_user(id), "test").await.unwrap();
+        assert_eq!(db_context.get_user_by_id(id).await.unwrap().version, 1);
+
+        let updated_user = UpdateUserSchema::new()
+            .with_name("Jorge".to_string())
+            .with_expected_version(Some(1))
+            .finalize()
+            .unwrap();
+        db_context
+            .update_user(id, &updated_user, "test")
+            .await
+            .unwrap();
+        assert_eq!(db_context.get_user_by_id(id).await.unwrap().version, 2);
+
+        let result = db_context.update_user(id, &updated_user, "test").await;
+        assert!(matches!(result, Err(ErrorKinsper::VersionMismatch(_))));
+        let result = db_context.delete_user(id, Some(1), "test").await;
+        assert!(matches!(result, Err(ErrorKinsper::VersionMismatch(_))));
+        let result = db_context.delete_user(missing_id, Some(1), "test").await;
+        assert!(matches!(result, Err(ErrorKinsper::NotFound(_))));
+
+        db_context.delete_user(id, Some(2), "test").await.unwrap();
+        db_context.restore_user(id, "test").await.unwrap();
+        assert_eq!(db_context.get_user_by_id(id).await.unwrap().version, 4);
+
+        purge(db_context, id).await;
+    }
+
+    // Un id que no existe o una version vieja se informan antes que un mail repetido
+    pub(crate) async fn when_update_user_given_missing_id_and_taken_mail_then_returns_not_found(
+        db_context: &impl UserRepository,
+        id: &str,
+        missing_id: &str,
+    ) {
+        db_context.add_user(&new_user(id), "test").await.unwrap();
+        let taken_mail = UpdateUserSchema::new()
+            .with_mail(new_user(id).mail)
+            .finalize()
+            .unwrap();
+
+        let result = db_context
+            .update_user(missing_id, &taken_mail, "test")
+            .await;
+        assert!(matches!(result, Err(ErrorKinsper::NotFound(_))));
+
+        db_context
+            .add_user(&new_user(missing_id), "test")
+            .await
+            .unwrap();
+        let stale = taken_mail.clone().with_expected_version(Some(2));
+        let result = db_context.update_user(missing_id, &stale, "test").await;
+        assert!(matches!(result, Err(ErrorKinsper::VersionMismatch(_))));
+        let result = db_context
+            .update_user(missing_id, &taken_mail, "test")
+            .await;
+        assert!(matches!(result, Err(ErrorKinsper::AlreadyExists(_))));
+
+        purge(db_context, id).await;
+        purge(db_context, missing_id).await;
+    }
+
+    // term tiene que ser una palabra que no usa ningun otro test
+    pub(crate) async fn when_get_users_given_text_query_with_tied_scores_then_pages_by_id(
+        db_context: &impl UserRepository,
+        term: &str,
+        ids: &[&str],
+    ) {
+        for id in ids {
+            let user = CreateUserScheme {
+                name: format!("{} Doe", term),
+                ..new_user(id)
+            };
+            db_context.add_user(&user, "test").await.unwrap();
+        }
+
+        let mut query = GetUsersScheme::new()
+            .with_text_query(Some(term.to_string()))
+            .with_limit(2);
+        let mut paged = Vec::new();
+        while let Ok(page) = db_context.get_users(&query).await {
+            let last = page.last().unwrap();
+            assert!(page.iter().all(|user| user.score == last.score));
+            query = query
+                .with_after_id(last.id.clone())
+                .with_after_score(last.score.unwrap());
+            paged.extend(page.into_iter().map(|user| user.id));
+        }
+        let mut expected: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        expected.sort();
+        assert_eq!(paged, expected);
+
+        for id in ids {
+            purge(db_context, id).await;
+        }
+    }
+
+    pub(crate) async fn when_mutate_users_then_audit_events_are_recorded_with_caller_and_values(
+        db_context: &impl UserRepository,
+        id: &str,
+    ) {
+        let last_event_id = db_context.last_audit_event_id().await.unwrap();
+        db_context.add_user(&new_user(id), "alice").await.unwrap();
+        let updated_user = UpdateUserSchema::new()
+            .with_name("Jorge".to_string())
+            .finalize()
+            .unwrap();
+        db_context
+            .update_user(id, &updated_user, "bob")
+            .await
+            .unwrap();
+        db_context.delete_user(id, None, "bob").await.unwrap();
+        db_context.restore_user(id, "carol").await.unwrap();
+        // Una escritura que falla no deja evento
+        assert!(db_context.add_user(&new_user(id), "alice").await.is_err());
+
+        let query = GetAuditEventsScheme::new().with_user_id(Some(id.to_string()));
+        let query = match last_event_id {
+            Some(last_event_id) => query.with_after_id(last_event_id),
+            None => query,
+        };
+        let events = db_context.list_audit_events(&query).await.unwrap();
+        let operations: Vec<&str> = events.iter().map(|e| e.operation.as_str()).collect();
+        assert_eq!(operations, vec!["CREATE", "UPDATE", "DELETE", "RESTORE"]);
+        assert!(events.windows(2).all(|pair| pair[0].id < pair[1].id));
+        assert!(db_context.last_audit_event_id().await.unwrap() >= Some(events[3].id));
+
+        let create = &events[0];
+        assert_eq!(create.caller, "alice");
+        assert_eq!(create.user_id.as_deref(), Some(id));
+        assert!(create.before_value.is_none());
+        assert!(create.after_value.as_ref().unwrap().contains("Fede"));
+
+        let update = &events[1];
+        assert_eq!(update.caller, "bob");
+        assert!(update.before_value.as_ref().unwrap().contains("Fede"));
+        assert!(update.after_value.as_ref().unwrap().contains("Jorge"));
+
+        let restore = &events[3];
+        assert_eq!(restore.caller, "carol");
+        assert!(!restore
+            .before_value
+            .as_ref()
+            .unwrap()
+            .contains(r#""deleted_at":null"#));
+        assert!(restore
+            .after_value
+            .as_ref()
+            .unwrap()
+            .contains(r#""deleted_at":null"#));
+
+        let filtered = db_context
+            .list_audit_events(&query.with_operation(Some(AuditOperation::Update)))
+            .await
+            .unwrap();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].id, update.id);
+
+        purge(db_context, id).await;
+    }
+}
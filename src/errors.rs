@@ -1,3 +1,28 @@
+use std::time::Duration;
+
+use prost::Message;
+use rpc::bad_request::FieldViolation;
+use sqlx::error::DatabaseError;
+use sqlx::mysql::MySqlDatabaseError;
+use sqlx::sqlite::SqliteError;
+
+// Mensajes de google.rpc para los detalles de error que viajan en el trailer
+// grpc-status-details-bin
+pub mod rpc {
+    tonic::include_proto!("google.rpc");
+}
+
+pub const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+pub const ERROR_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.ErrorInfo";
+pub const RETRY_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.RetryInfo";
+// Dominio de los ErrorInfo, las reasons son unicas dentro de el
+pub const ERROR_DOMAIN: &str = "user_service";
+
+// Espera sugerida antes de reintentar cuando la base no esta disponible o la
+// transaccion choco con otra
+pub const UNAVAILABLE_RETRY_DELAY: Duration = Duration::from_secs(1);
+pub const CONFLICT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
 #[derive(Debug, PartialEq)]
 pub enum ErrorKinsper {
     InternalServer(String),
@@ -5,42 +30,339 @@ pub enum ErrorKinsper {
     ConnectionError(String),
     MySqlError(String),
     UpdateSchemeError(String),
+    MigrationError(String),
     InvalidEmail(String),
     InvalidId(String),
+    InvalidPageToken(String),
+    InvalidTimestamp(String),
+    InvalidArgument(String),
+    // Uno o mas campos invalidos de un pedido, ver validation
+    InvalidRequest(Vec<FieldViolation>),
     InternalValidationError(String),
     NotFound(String),
     AlreadyExists(String),
+    VersionMismatch(String),
+    // Deadlock o lock que no se pudo tomar a tiempo, la transaccion se revirtio
+    TransactionConflict(String),
+    // Falta el token del pedido o no es valido
+    Unauthenticated(String),
+    // La identidad autenticada no tiene un rol que permita el RPC
+    PermissionDenied(String),
+    // ResetUserTable en un servidor que no arranco en modo development o test
+    ResetDisabled(String),
     Unknown,
 }
 
+// Nombre del indice unico sobre users.mail en todas las migraciones
+pub const MAIL_UNIQUE_INDEX: &str = "users_mail_key";
+
+impl ErrorKinsper {
+    // field es la columna unica que colisiono: id o mail
+    pub fn already_exists(field: &str) -> Self {
+        ErrorKinsper::AlreadyExists(format!("A user with the same {} already exists", field))
+    }
+
+    // Codigo estable que viaja en ErrorInfo.reason. Los clientes dependen de estos
+    // valores, no se cambian aunque se renombre la variante
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ErrorKinsper::InternalServer(_) => "INTERNAL",
+            ErrorKinsper::InvalidUri(_) => "INVALID_URI",
+            ErrorKinsper::ConnectionError(_) => "DATABASE_UNAVAILABLE",
+            ErrorKinsper::MySqlError(_) => "DATABASE_ERROR",
+            ErrorKinsper::UpdateSchemeError(_) => "INVALID_UPDATE",
+            ErrorKinsper::MigrationError(_) => "MIGRATION_FAILED",
+            ErrorKinsper::InvalidEmail(_) => "INVALID_MAIL",
+            ErrorKinsper::InvalidId(_) => "INVALID_ID",
+            ErrorKinsper::InvalidPageToken(_) => "INVALID_PAGE_TOKEN",
+            ErrorKinsper::InvalidTimestamp(_) => "INVALID_TIMESTAMP",
+            ErrorKinsper::InvalidArgument(_) => "INVALID_ARGUMENT",
+            ErrorKinsper::InvalidRequest(_) => "INVALID_REQUEST",
+            ErrorKinsper::InternalValidationError(_) => "VALIDATION_FAILED",
+            ErrorKinsper::NotFound(_) => "USER_NOT_FOUND",
+            ErrorKinsper::AlreadyExists(_) => "USER_ALREADY_EXISTS",
+            ErrorKinsper::VersionMismatch(_) => "VERSION_MISMATCH",
+            ErrorKinsper::TransactionConflict(_) => "TRANSACTION_CONFLICT",
+            ErrorKinsper::Unauthenticated(_) => "UNAUTHENTICATED",
+            ErrorKinsper::PermissionDenied(_) => "PERMISSION_DENIED",
+            ErrorKinsper::ResetDisabled(_) => "RESET_DISABLED",
+            ErrorKinsper::Unknown => "UNKNOWN",
+        }
+    }
+
+    // Solo los errores transitorios se pueden reintentar tal cual. VersionMismatch
+    // no, el cliente tiene que releer al usuario antes
+    pub fn retry_delay(&self) -> Option<Duration> {
+        match self {
+            ErrorKinsper::ConnectionError(_) => Some(UNAVAILABLE_RETRY_DELAY),
+            ErrorKinsper::TransactionConflict(_) => Some(CONFLICT_RETRY_DELAY),
+            _ => None,
+        }
+    }
+}
+
 impl From<sqlx::Error> for ErrorKinsper {
     fn from(err: sqlx::Error) -> Self {
         match err {
-            sqlx::Error::Database(e) if e.to_string().contains("Duplicate entry") => {
-                ErrorKinsper::AlreadyExists("Error duplicate entry".to_string())
+            sqlx::Error::Database(e) if is_duplicate_entry(e.as_ref()) => {
+                ErrorKinsper::already_exists(duplicated_field(e.as_ref()))
+            }
+            sqlx::Error::Database(e) if is_transaction_conflict(e.as_ref()) => {
+                ErrorKinsper::TransactionConflict(format!("Transaction aborted: {}", e))
             }
             sqlx::Error::RowNotFound => ErrorKinsper::NotFound("Error user not found".to_string()),
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed => {
+                ErrorKinsper::ConnectionError(format!("Database unavailable: {}", err))
+            }
             _ => ErrorKinsper::MySqlError(format!("Error from MySql: {}", err)),
         }
     }
 }
 
-use tonic::Status;
+// MySQL informa la clave duplicada en el mensaje ("Duplicate entry ..."), SQLite lo
+// hace con los codigos extendidos SQLITE_CONSTRAINT_PRIMARYKEY (1555) y
+// SQLITE_CONSTRAINT_UNIQUE (2067), y Postgres con el SQLSTATE unique_violation (23505)
+fn is_duplicate_entry(err: &dyn sqlx::error::DatabaseError) -> bool {
+    err.to_string().contains("Duplicate entry")
+        || matches!(
+            err.code().as_deref(),
+            Some("1555") | Some("2067") | Some("23505")
+        )
+}
+
+// MySQL: ER_LOCK_WAIT_TIMEOUT (1205) y ER_LOCK_DEADLOCK (1213). SQLite: SQLITE_BUSY (5)
+// y SQLITE_LOCKED (6), el codigo extendido los tiene en el byte bajo. Postgres:
+// serialization_failure (40001), deadlock_detected (40P01) y lock_not_available (55P03)
+fn is_transaction_conflict(err: &dyn DatabaseError) -> bool {
+    if let Some(err) = err.try_downcast_ref::<MySqlDatabaseError>() {
+        return matches!(err.number(), 1205 | 1213);
+    }
+    if err.try_downcast_ref::<SqliteError>().is_some() {
+        return err
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, 5 | 6));
+    }
+    matches!(
+        err.code().as_deref(),
+        Some("40001") | Some("40P01") | Some("55P03")
+    )
+}
+
+// Postgres informa el indice en constraint(), MySQL lo incluye en el mensaje
+// ("... for key 'users.users_mail_key'") y SQLite la columna ("users.mail").
+// Cualquier otra colision es sobre la primary key
+fn duplicated_field(err: &dyn sqlx::error::DatabaseError) -> &'static str {
+    let message = err.to_string();
+    if err.constraint() == Some(MAIL_UNIQUE_INDEX)
+        || message.contains(MAIL_UNIQUE_INDEX)
+        || message.contains("users.mail")
+    {
+        "mail"
+    } else {
+        "id"
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for ErrorKinsper {
+    fn from(err: sqlx::migrate::MigrateError) -> Self {
+        match err {
+            sqlx::migrate::MigrateError::Execute(err) => err.into(),
+            _ => ErrorKinsper::MigrationError(format!("Error running migrations: {}", err)),
+        }
+    }
+}
+
+use tonic::{Code, Status};
 
 impl From<ErrorKinsper> for Status {
     fn from(err: ErrorKinsper) -> Self {
-        match err {
-            ErrorKinsper::InternalServer(msg) => Status::internal(msg),
-            ErrorKinsper::InvalidUri(msg) => Status::internal(msg),
-            ErrorKinsper::ConnectionError(msg) => Status::internal(msg),
-            ErrorKinsper::MySqlError(msg) => Status::internal(msg),
-            ErrorKinsper::UpdateSchemeError(msg) => Status::internal(msg),
-            ErrorKinsper::InvalidEmail(msg) => Status::invalid_argument(msg),
-            ErrorKinsper::InvalidId(msg) => Status::invalid_argument(msg),
-            ErrorKinsper::InternalValidationError(msg) => Status::internal(msg),
-            ErrorKinsper::NotFound(msg) => Status::not_found(msg),
-            ErrorKinsper::AlreadyExists(msg) => Status::already_exists(msg),
-            ErrorKinsper::Unknown => Status::internal("Unknown error"),
+        let reason = err.reason();
+        let retry_delay = err.retry_delay();
+        let (code, message, field_violations) = match err {
+            ErrorKinsper::InternalServer(msg) => (Code::Internal, msg, None),
+            ErrorKinsper::InvalidUri(msg) => (Code::Internal, msg, None),
+            ErrorKinsper::ConnectionError(msg) => (Code::Unavailable, msg, None),
+            ErrorKinsper::MySqlError(msg) => (Code::Internal, msg, None),
+            ErrorKinsper::UpdateSchemeError(msg) => (Code::Internal, msg, None),
+            ErrorKinsper::MigrationError(msg) => (Code::Internal, msg, None),
+            ErrorKinsper::InvalidEmail(msg) => (Code::InvalidArgument, msg, None),
+            ErrorKinsper::InvalidId(msg) => (Code::InvalidArgument, msg, None),
+            ErrorKinsper::InvalidPageToken(msg) => (Code::InvalidArgument, msg, None),
+            ErrorKinsper::InvalidTimestamp(msg) => (Code::InvalidArgument, msg, None),
+            ErrorKinsper::InvalidArgument(msg) => (Code::InvalidArgument, msg, None),
+            // El mensaje junta las descripciones para los clientes que no leen los detalles
+            ErrorKinsper::InvalidRequest(violations) => (
+                Code::InvalidArgument,
+                violations
+                    .iter()
+                    .map(|violation| violation.description.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+                Some(violations),
+            ),
+            ErrorKinsper::InternalValidationError(msg) => (Code::Internal, msg, None),
+            ErrorKinsper::NotFound(msg) => (Code::NotFound, msg, None),
+            ErrorKinsper::AlreadyExists(msg) => (Code::AlreadyExists, msg, None),
+            // Conflicto de concurrencia: el cliente tiene que releer y reintentar
+            ErrorKinsper::VersionMismatch(msg) => (Code::Aborted, msg, None),
+            ErrorKinsper::TransactionConflict(msg) => (Code::Aborted, msg, None),
+            ErrorKinsper::Unauthenticated(msg) => (Code::Unauthenticated, msg, None),
+            ErrorKinsper::PermissionDenied(msg) => (Code::PermissionDenied, msg, None),
+            ErrorKinsper::ResetDisabled(msg) => (Code::FailedPrecondition, msg, None),
+            ErrorKinsper::Unknown => (Code::Internal, "Unknown error".to_string(), None),
+        };
+
+        let mut details = vec![prost_types::Any {
+            type_url: ERROR_INFO_TYPE_URL.to_string(),
+            value: rpc::ErrorInfo {
+                reason: reason.to_string(),
+                domain: ERROR_DOMAIN.to_string(),
+                metadata: Default::default(),
+            }
+            .encode_to_vec(),
+        }];
+        if let Some(retry_delay) = retry_delay {
+            details.push(prost_types::Any {
+                type_url: RETRY_INFO_TYPE_URL.to_string(),
+                value: rpc::RetryInfo {
+                    retry_delay: prost_types::Duration::try_from(retry_delay).ok(),
+                }
+                .encode_to_vec(),
+            });
+        }
+        if let Some(field_violations) = field_violations {
+            details.push(prost_types::Any {
+                type_url: BAD_REQUEST_TYPE_URL.to_string(),
+                value: rpc::BadRequest { field_violations }.encode_to_vec(),
+            });
         }
+
+        let status = rpc::Status {
+            code: code as i32,
+            message: message.clone(),
+            details,
+        };
+        Status::with_details(code, message, status.encode_to_vec().into())
+    }
+}
+
+// Detalles de google.rpc que trae un Status, del lado del cliente
+#[derive(Debug, Default, PartialEq)]
+pub struct ErrorDetails {
+    pub error_info: Option<rpc::ErrorInfo>,
+    pub retry_info: Option<rpc::RetryInfo>,
+    pub bad_request: Option<rpc::BadRequest>,
+}
+
+impl ErrorDetails {
+    // Los detalles que no se conocen o no se pueden decodificar se ignoran
+    pub fn from_status(status: &Status) -> Self {
+        let mut details = ErrorDetails::default();
+        let Ok(status) = rpc::Status::decode(status.details()) else {
+            return details;
+        };
+        for detail in status.details {
+            let value = detail.value.as_slice();
+            match detail.type_url.as_str() {
+                ERROR_INFO_TYPE_URL => details.error_info = rpc::ErrorInfo::decode(value).ok(),
+                RETRY_INFO_TYPE_URL => details.retry_info = rpc::RetryInfo::decode(value).ok(),
+                BAD_REQUEST_TYPE_URL => details.bad_request = rpc::BadRequest::decode(value).ok(),
+                _ => {}
+            }
+        }
+        details
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.error_info.as_ref().map(|info| info.reason.as_str())
+    }
+
+    pub fn retry_delay(&self) -> Option<Duration> {
+        self.retry_info
+            .as_ref()
+            .and_then(|info| info.retry_delay.clone())
+            .and_then(|delay| Duration::try_from(delay).ok())
+    }
+}
+
+// Lee el google.rpc.BadRequest de los detalles de un Status, si lo tiene
+pub fn bad_request_details(status: &Status) -> Option<rpc::BadRequest> {
+    ErrorDetails::from_status(status).bad_request
+}
+
+#[cfg(test)]
+mod errors_tests {
+    use std::str::FromStr;
+
+    use sqlx::sqlite::SqliteConnectOptions;
+    use sqlx::{ConnectOptions, Executor};
+    use tempfile::NamedTempFile;
+    use tonic::{Code, Status};
+
+    use crate::errors::{
+        ErrorDetails, ErrorKinsper, CONFLICT_RETRY_DELAY, ERROR_DOMAIN, UNAVAILABLE_RETRY_DELAY,
+    };
+
+    #[test]
+    fn test01_when_error_into_status_then_has_error_info_with_reason() {
+        let status: Status = ErrorKinsper::NotFound("Error user not found".to_string()).into();
+
+        let details = ErrorDetails::from_status(&status);
+
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "Error user not found");
+        assert_eq!(details.reason(), Some("USER_NOT_FOUND"));
+        assert_eq!(details.error_info.unwrap().domain, ERROR_DOMAIN);
+        assert_eq!(details.retry_info, None);
+        assert_eq!(details.bad_request, None);
+    }
+
+    #[test]
+    fn test02_when_transient_error_into_status_then_has_retry_info() {
+        let unavailable: ErrorKinsper = sqlx::Error::PoolTimedOut.into();
+        let status: Status = unavailable.into();
+        let details = ErrorDetails::from_status(&status);
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(details.reason(), Some("DATABASE_UNAVAILABLE"));
+        assert_eq!(details.retry_delay(), Some(UNAVAILABLE_RETRY_DELAY));
+
+        let status: Status = ErrorKinsper::TransactionConflict("deadlock".to_string()).into();
+        let details = ErrorDetails::from_status(&status);
+
+        assert_eq!(status.code(), Code::Aborted);
+        assert_eq!(details.reason(), Some("TRANSACTION_CONFLICT"));
+        assert_eq!(details.retry_delay(), Some(CONFLICT_RETRY_DELAY));
+
+        let status: Status = ErrorKinsper::VersionMismatch("version".to_string()).into();
+        assert_eq!(status.code(), Code::Aborted);
+        assert_eq!(ErrorDetails::from_status(&status).retry_info, None);
+    }
+
+    #[tokio::test]
+    async fn test03_when_database_is_locked_then_error_is_transaction_conflict() {
+        let file = NamedTempFile::new().unwrap();
+        let options = SqliteConnectOptions::from_str(file.path().to_str().unwrap())
+            .unwrap()
+            .busy_timeout(std::time::Duration::ZERO);
+        let mut holder = options.connect().await.unwrap();
+        let mut other = options.connect().await.unwrap();
+
+        holder.execute("BEGIN IMMEDIATE").await.unwrap();
+        let err: ErrorKinsper = other.execute("BEGIN IMMEDIATE").await.unwrap_err().into();
+
+        assert!(matches!(err, ErrorKinsper::TransactionConflict(_)));
+    }
+
+    #[test]
+    fn test04_when_status_has_no_details_then_error_details_are_empty() {
+        let status = Status::internal("plain");
+
+        assert_eq!(ErrorDetails::from_status(&status), ErrorDetails::default());
     }
 }
@@ -1,34 +1,148 @@
+use crate::auth::AuthIdentity;
+use crate::authorization::{AuthorizationPolicy, Rpc};
 use crate::data::context::Database;
-use crate::data::scheme::{CreateUserScheme, UpdateUserSchema};
+use crate::data::model::{AuditEventModel, AuditOperation, UserModel};
+use crate::data::repository::UserRepository;
+use crate::data::repository::UserStream;
+use crate::data::scheme::{
+    CreateUserScheme, GetAuditEventsScheme, GetUsersScheme, UpdateUserSchema, UserSortField,
+};
+use crate::data::{snapshot_table_name, QUERY_LIMIT};
+use crate::errors::rpc::bad_request::FieldViolation;
 use crate::errors::ErrorKinsper;
-use crate::{validate_mail, LIMIT_STREAM_QUEUE};
-use tokio::sync::mpsc;
+use crate::validation::{RequestValidator, Validate};
+use crate::{
+    normalize_mail, IdFormat, IdPolicy, MailLocalPartPolicy, ServerEnvironment,
+    CALLER_METADATA_KEY, LIMIT_STREAM_QUEUE, PURGE_RETENTION_DAYS, WATCH_POLL_INTERVAL_MS,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use futures::StreamExt;
+use prost_types::Timestamp;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
 use user_service::user_service_server::UserService;
 use user_service::{
-    CreateUserRequest, CreateUserResponse, DeleteUserRequest, DeleteUserResponse,
-    GetAllUserRequest, GetUserRequest, GetUserResponse, ResetUserTableRequest,
-    ResetUserTableResponse, UpdateUserMailRequest, UpdateUserMailResponse, UpdateUserNameRequest,
-    UpdateUserNameResponse, UserId,
+    AuditEvent, CreateUserRequest, CreateUserResponse, DeleteUserRequest, DeleteUserResponse,
+    GetAllUserRequest, GetUserByMailRequest, GetUserRequest, GetUserResponse,
+    ListAuditEventsRequest, ListAuditEventsResponse, PurgeDeletedUsersRequest,
+    PurgeDeletedUsersResponse, ResetUserTableRequest, ResetUserTableResponse, RestoreUserRequest,
+    RestoreUserResponse, SearchUsersRequest, UpdateUserMailRequest, UpdateUserMailResponse,
+    UpdateUserNameRequest, UpdateUserNameResponse, UserId, WatchUsersRequest, WatchUsersResponse,
 };
 
 pub mod user_service {
     tonic::include_proto!("user_service");
 }
 
-pub struct MyUserService {
-    pub db_context: Database,
+pub struct MyUserService<R: UserRepository = Database> {
+    pub db_context: Arc<R>,
+    // Contador de cambios hechos por esta instancia, despierta a los WatchUsers
+    changes: watch::Sender<u64>,
+    mail_policy: MailLocalPartPolicy,
+    id_format: IdFormat,
+    id_policy: IdPolicy,
+    // Tope de usuarios o eventos por pagina y largo de las listas sin paginar
+    query_limit: u32,
+    // Mensajes que se encolan por stream de respuesta antes de esperar al cliente
+    stream_queue_size: usize,
+    authorization_policy: AuthorizationPolicy,
+    // ResetUserTable solo se atiende en development o test y con database_name
+    // como confirmacion
+    environment: ServerEnvironment,
+    database_name: String,
 }
 
-impl MyUserService {
-    async fn update_user_helper<F, R>(
+impl<R: UserRepository> MyUserService<R> {
+    pub fn new(db_context: R) -> Self {
+        let (changes, _) = watch::channel(0);
+        MyUserService {
+            db_context: Arc::new(db_context),
+            changes,
+            mail_policy: MailLocalPartPolicy::default(),
+            id_format: IdFormat::default(),
+            id_policy: IdPolicy::default(),
+            query_limit: QUERY_LIMIT,
+            stream_queue_size: LIMIT_STREAM_QUEUE,
+            authorization_policy: AuthorizationPolicy::default(),
+            environment: ServerEnvironment::default(),
+            database_name: String::new(),
+        }
+    }
+
+    pub fn with_mail_policy(mut self, mail_policy: MailLocalPartPolicy) -> Self {
+        self.mail_policy = mail_policy;
+        self
+    }
+
+    pub fn with_id_format(mut self, id_format: IdFormat) -> Self {
+        self.id_format = id_format;
+        self
+    }
+
+    pub fn with_id_policy(mut self, id_policy: IdPolicy) -> Self {
+        self.id_policy = id_policy;
+        self
+    }
+
+    pub fn with_query_limit(mut self, query_limit: u32) -> Self {
+        self.query_limit = query_limit;
+        self
+    }
+
+    pub fn with_stream_queue_size(mut self, stream_queue_size: usize) -> Self {
+        self.stream_queue_size = stream_queue_size;
+        self
+    }
+
+    pub fn with_authorization_policy(mut self, authorization_policy: AuthorizationPolicy) -> Self {
+        self.authorization_policy = authorization_policy;
+        self
+    }
+
+    pub fn with_environment(mut self, environment: ServerEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    pub fn with_database_name(mut self, database_name: &str) -> Self {
+        self.database_name = database_name.to_string();
+        self
+    }
+
+    // Lo primero de cada RPC, antes de validar el pedido, ver authorization
+    fn authorize<T>(&self, request: &Request<T>, rpc: Rpc) -> Result<(), ErrorKinsper> {
+        self.authorization_policy
+            .authorize(AuthIdentity::from_request(request), rpc)
+    }
+
+    // Todos los RPCs validan el pedido antes de hacer nada, ver validation
+    fn validate<T: Validate>(&self, request: &T) -> Result<(), ErrorKinsper> {
+        RequestValidator::new(&self.id_policy, self.mail_policy).validate(request)
+    }
+
+    // Los mails se guardan normalizados, asi el indice unico no distingue mayusculas
+    fn normalize_mail(&self, mail: &str) -> String {
+        normalize_mail(mail, self.mail_policy)
+    }
+
+    // Avisa a los WatchUsers abiertos si la operacion modifico usuarios
+    fn notify_change<T>(&self, result: Result<T, ErrorKinsper>) -> Result<T, ErrorKinsper> {
+        if result.is_ok() {
+            self.changes.send_modify(|changes| *changes += 1);
+        }
+        result
+    }
+
+    async fn update_user_helper<F, T>(
         &self,
         id: &str,
+        caller: &str,
         schema_creator: F,
-        response_creator: fn() -> R,
-    ) -> Result<Response<R>, Status>
+        response_creator: fn() -> T,
+    ) -> Result<Response<T>, Status>
     where
         F: FnOnce() -> Result<UpdateUserSchema, ErrorKinsper>,
     {
@@ -38,36 +152,323 @@ impl MyUserService {
         // std::thread::sleep(std::time::Duration::from_secs(5)); // For visualize ops blocking in runtime thread
 
         Ok(self
-            .db_context
-            .update_user(id, &updated_schema)
-            .await
+            .notify_change(
+                self.db_context
+                    .update_user(id, &updated_schema, caller)
+                    .await,
+            )
             .map(|_| Response::new(response_creator()))?)
     }
 
-    fn id_to_str<'a>(&self, id: &'a Option<UserId>) -> Result<&'a str, Status> {
+    // Identidad de quien hace el pedido para la auditoria: la del token si el servidor
+    // exige autenticacion, sino la metadata x-caller-id si viene o la direccion remota
+    fn caller<T>(&self, request: &Request<T>) -> String {
+        if let Some(identity) = AuthIdentity::from_request(request) {
+            return identity.subject.clone();
+        }
+        request
+            .metadata()
+            .get(CALLER_METADATA_KEY)
+            .and_then(|caller| caller.to_str().ok())
+            .map(str::to_string)
+            .or_else(|| request.remote_addr().map(|addr| addr.to_string()))
+            .unwrap_or_else(|| "anonymous".to_string())
+    }
+
+    // El formato del id ya lo controla validate, aca solo se extrae
+    fn id_to_str<'a>(&self, id: &'a Option<UserId>) -> Result<&'a str, ErrorKinsper> {
         match id {
             Some(id) => Ok(&id.id),
-            None => Err(ErrorKinsper::InvalidId("Invalid id".to_string()).into()),
+            None => Err(ErrorKinsper::InvalidId("Invalid id: missing".to_string())),
+        }
+    }
+}
+
+// El page token es el id del ultimo usuario de la pagina codificado en hex,
+// asi los clientes lo tratan como un valor opaco
+fn encode_page_token(last_id: &str) -> String {
+    last_id
+        .bytes()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn decode_page_token(page_token: &str) -> Result<String, ErrorKinsper> {
+    let invalid = || ErrorKinsper::InvalidPageToken("Invalid page token".to_string());
+
+    let bytes = page_token
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => std::str::from_utf8(pair)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        })
+        .collect::<Result<Vec<u8>, ErrorKinsper>>()?;
+
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+// En SearchUsers el page token lleva tambien el valor de la columna de orden del
+// ultimo usuario, para poder paginar por columnas que se repiten. En la busqueda
+// de texto completo ese valor es el score
+fn encode_search_page_token(sort_by: UserSortField, user: &UserModel) -> String {
+    let key = match user.score {
+        Some(score) => serde_json::json!(score),
+        None => serde_json::json!(sort_by.value(user)),
+    };
+    encode_page_token(&serde_json::json!([key, user.id]).to_string())
+}
+
+fn with_search_page_token(
+    query: GetUsersScheme,
+    page_token: &str,
+) -> Result<GetUsersScheme, ErrorKinsper> {
+    let invalid = || ErrorKinsper::InvalidPageToken("Invalid page token".to_string());
+
+    let (key, after_id): (serde_json::Value, String) =
+        serde_json::from_str(&decode_page_token(page_token)?).map_err(|_| invalid())?;
+    let query = query.with_after_id(after_id);
+    match key {
+        serde_json::Value::String(after_key) => Ok(query.with_after_key(after_key)),
+        serde_json::Value::Number(after_score) => after_score
+            .as_f64()
+            .map(|after_score| query.with_after_score(after_score))
+            .ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+fn user_sort_field_from_proto(sort_by: i32) -> Result<UserSortField, ErrorKinsper> {
+    use user_service::UserSortField as Proto;
+
+    match Proto::from_i32(sort_by) {
+        Some(Proto::Id) => Ok(UserSortField::Id),
+        Some(Proto::Name) => Ok(UserSortField::Name),
+        Some(Proto::Mail) => Ok(UserSortField::Mail),
+        None => Err(ErrorKinsper::InvalidArgument(format!(
+            "Invalid sort field: {}",
+            sort_by
+        ))),
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    Some(value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+// Envia los usuarios de una pagina por el stream de respuesta. Se lee un usuario
+// por adelantado para saber si el actual es el ultimo de la pagina, y en ese caso
+// se le agrega el next_page_token. Si el cliente se desconecta, el send falla y al
+// descartar el stream de usuarios se corta la query en la base de datos.
+fn stream_page<F>(
+    mut user: UserModel,
+    mut users: UserStream,
+    page_size: u32,
+    queue_size: usize,
+    page_token: F,
+) -> ReceiverStream<Result<GetUserResponse, Status>>
+where
+    F: Fn(&UserModel) -> String + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(queue_size);
+
+    tokio::spawn(async move {
+        let mut sent = 0;
+        loop {
+            sent += 1;
+            let end_of_page = page_size > 0 && sent == page_size;
+            let next = users.next().await;
+
+            let next_page_token = if end_of_page && matches!(next, Some(Ok(_))) {
+                page_token(&user)
+            } else {
+                String::new()
+            };
+            let response = GetUserResponse {
+                next_page_token,
+                ..user.into()
+            };
+            if tx.send(Ok(response)).await.is_err() {
+                log::error!("Channel send error");
+                break;
+            }
+
+            // Un error al leer el siguiente usuario se envia aunque la pagina este
+            // completa, si no el cliente veria un final del listado sin token
+            match next {
+                Some(Ok(next)) if !end_of_page => user = next,
+                Some(Err(err)) => {
+                    let _ = tx.send(Err(err.into())).await;
+                    break;
+                }
+                _ => break,
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+// El resume token de WatchUsers es el id del ultimo evento de auditoria enviado
+fn decode_resume_token(resume_token: &str) -> Result<i64, ErrorKinsper> {
+    decode_page_token(resume_token)
+        .ok()
+        .and_then(|id| id.parse::<i64>().ok())
+        .ok_or_else(|| ErrorKinsper::InvalidArgument("Invalid resume token".to_string()))
+}
+
+pub fn to_timestamp(at: DateTime<Utc>) -> Timestamp {
+    Timestamp {
+        seconds: at.timestamp(),
+        nanos: at.timestamp_subsec_nanos() as i32,
+    }
+}
+
+pub fn from_timestamp(timestamp: &Timestamp) -> Result<DateTime<Utc>, ErrorKinsper> {
+    u32::try_from(timestamp.nanos)
+        .ok()
+        .and_then(|nanos| Utc.timestamp_opt(timestamp.seconds, nanos).single())
+        .ok_or_else(|| ErrorKinsper::InvalidTimestamp("Invalid timestamp".to_string()))
+}
+
+// expected_version = 0 significa que no se controla la version
+fn expected_version(version: u64) -> Result<Option<i64>, ErrorKinsper> {
+    match version {
+        0 => Ok(None),
+        version => i64::try_from(version).map(Some).map_err(|_| {
+            ErrorKinsper::VersionMismatch(format!("Version mismatch: expected {}", version))
+        }),
+    }
+}
+
+fn from_optional_timestamp(
+    timestamp: &Option<Timestamp>,
+) -> Result<Option<DateTime<Utc>>, ErrorKinsper> {
+    timestamp.as_ref().map(from_timestamp).transpose()
+}
+
+fn audit_operation_from_proto(operation: i32) -> Result<Option<AuditOperation>, ErrorKinsper> {
+    use user_service::AuditOperation as Proto;
+
+    match Proto::from_i32(operation) {
+        Some(Proto::Unspecified) => Ok(None),
+        Some(Proto::Create) => Ok(Some(AuditOperation::Create)),
+        Some(Proto::Update) => Ok(Some(AuditOperation::Update)),
+        Some(Proto::Delete) => Ok(Some(AuditOperation::Delete)),
+        Some(Proto::Restore) => Ok(Some(AuditOperation::Restore)),
+        Some(Proto::Purge) => Ok(Some(AuditOperation::Purge)),
+        Some(Proto::Reset) => Ok(Some(AuditOperation::Reset)),
+        None => Err(ErrorKinsper::InvalidArgument(format!(
+            "Invalid audit operation: {}",
+            operation
+        ))),
+    }
+}
+
+fn audit_operation_to_proto(operation: &str) -> user_service::AuditOperation {
+    use user_service::AuditOperation as Proto;
+
+    match operation.parse::<AuditOperation>() {
+        Ok(AuditOperation::Create) => Proto::Create,
+        Ok(AuditOperation::Update) => Proto::Update,
+        Ok(AuditOperation::Delete) => Proto::Delete,
+        Ok(AuditOperation::Restore) => Proto::Restore,
+        Ok(AuditOperation::Purge) => Proto::Purge,
+        Ok(AuditOperation::Reset) => Proto::Reset,
+        Err(_) => Proto::Unspecified,
+    }
+}
+
+impl From<AuditEventModel> for AuditEvent {
+    fn from(event: AuditEventModel) -> Self {
+        AuditEvent {
+            id: event.id as u64,
+            operation: audit_operation_to_proto(&event.operation).into(),
+            user_id: event.user_id.unwrap_or_default(),
+            before: event.before_value.unwrap_or_default(),
+            after: event.after_value.unwrap_or_default(),
+            caller: event.caller,
+            created_at: Some(to_timestamp(event.created_at)),
+        }
+    }
+}
+
+impl TryFrom<AuditEventModel> for WatchUsersResponse {
+    type Error = ErrorKinsper;
+
+    fn try_from(event: AuditEventModel) -> Result<Self, Self::Error> {
+        // Para los borrados el ultimo estado conocido es el anterior al cambio
+        let user = event
+            .after_value
+            .as_ref()
+            .or(event.before_value.as_ref())
+            .map(|user| serde_json::from_str::<UserModel>(user))
+            .transpose()
+            .map_err(|err| {
+                ErrorKinsper::InternalServer(format!("Error deserializing audit event: {}", err))
+            })?;
+
+        Ok(WatchUsersResponse {
+            resume_token: encode_page_token(&event.id.to_string()),
+            operation: audit_operation_to_proto(&event.operation).into(),
+            user_id: event.user_id.unwrap_or_default(),
+            user: user.map(GetUserResponse::from),
+            caller: event.caller,
+            changed_at: Some(to_timestamp(event.created_at)),
+        })
+    }
+}
+
+impl From<UserModel> for GetUserResponse {
+    fn from(user: UserModel) -> Self {
+        GetUserResponse {
+            id: Some(UserId { id: user.id }),
+            name: user.name,
+            mail: user.mail,
+            next_page_token: String::new(),
+            deleted: user.deleted_at.is_some(),
+            created_at: Some(to_timestamp(user.created_at)),
+            updated_at: Some(to_timestamp(user.updated_at)),
+            version: user.version as u64,
+            score: user.score.unwrap_or_default(),
         }
     }
 }
 
 #[tonic::async_trait]
-impl UserService for MyUserService {
+impl<R: UserRepository> UserService for MyUserService<R> {
     async fn get_user(
         &self,
         request: Request<GetUserRequest>,
     ) -> Result<Response<GetUserResponse>, Status> {
+        self.authorize(&request, Rpc::GetUser)?;
+        self.validate(request.get_ref())?;
         let id = self.id_to_str(&request.get_ref().id)?;
         log::info!("[GET_USER] Got a request from {:?}", request.remote_addr());
 
         let user = self.db_context.get_user_by_id(id).await?;
 
-        Ok(Response::new(GetUserResponse {
-            id: Some(UserId { id: user.id }),
-            name: user.name,
-            mail: user.mail,
-        }))
+        Ok(Response::new(user.into()))
+    }
+
+    async fn get_user_by_mail(
+        &self,
+        request: Request<GetUserByMailRequest>,
+    ) -> Result<Response<GetUserResponse>, Status> {
+        self.authorize(&request, Rpc::GetUserByMail)?;
+        self.validate(request.get_ref())?;
+        let mail = self.normalize_mail(&request.get_ref().mail);
+        log::info!(
+            "[GET_USER_BY_MAIL] Got a request from {:?}",
+            request.remote_addr()
+        );
+
+        let user = self.db_context.get_user_by_mail(&mail).await?;
+
+        Ok(Response::new(user.into()))
     }
 
     type GetAllUsersStream = ReceiverStream<Result<GetUserResponse, Status>>;
@@ -76,63 +477,143 @@ impl UserService for MyUserService {
         &self,
         request: Request<GetAllUserRequest>,
     ) -> Result<Response<Self::GetAllUsersStream>, Status> {
+        self.authorize(&request, Rpc::GetAllUsers)?;
+        self.validate(request.get_ref())?;
         log::info!("[GET_USERS] Got a request from {:?}", request.remote_addr());
 
-        let (tx, rx) = mpsc::channel(LIMIT_STREAM_QUEUE);
+        let req = request.get_ref();
 
-        let users = self
-            .db_context
-            .get_users(Some(request.get_ref().limit))
-            .await?;
-        tokio::spawn(async move {
-            for user in users {
-                if tx
-                    .send(Ok(GetUserResponse {
-                        id: Some(UserId { id: user.id }),
-                        name: user.name,
-                        mail: user.mail,
-                    }))
-                    .await
-                    .is_err()
-                {
-                    log::error!("Channel send error");
-                    break;
-                }
-            }
-        });
+        // Con page_size se pide un usuario de mas para saber si hay otra pagina
+        let page_size = req.page_size.min(self.query_limit);
+        let mut query = if page_size > 0 {
+            GetUsersScheme::new().with_limit(page_size + 1)
+        } else if req.limit > 0 {
+            GetUsersScheme::new().with_limit(req.limit)
+        } else {
+            GetUsersScheme::new().with_limit(self.query_limit)
+        }
+        .with_include_deleted(req.include_deleted)
+        .with_created_after(from_optional_timestamp(&req.created_after)?)
+        .with_created_before(from_optional_timestamp(&req.created_before)?)
+        .with_updated_after(from_optional_timestamp(&req.updated_after)?)
+        .with_updated_before(from_optional_timestamp(&req.updated_before)?);
+        if !req.page_token.is_empty() {
+            query = query.with_after_id(decode_page_token(&req.page_token)?);
+        }
 
-        Ok(Response::new(ReceiverStream::new(rx)))
+        let mut users = self.db_context.stream_users(&query);
+        let user = match users.next().await {
+            Some(user) => user?,
+            None => return Err(ErrorKinsper::NotFound("No users found.".to_string()).into()),
+        };
+
+        Ok(Response::new(stream_page(
+            user,
+            users,
+            page_size,
+            self.stream_queue_size,
+            |user| encode_page_token(&user.id),
+        )))
+    }
+
+    type SearchUsersStream = ReceiverStream<Result<GetUserResponse, Status>>;
+
+    async fn search_users(
+        &self,
+        request: Request<SearchUsersRequest>,
+    ) -> Result<Response<Self::SearchUsersStream>, Status> {
+        self.authorize(&request, Rpc::SearchUsers)?;
+        self.validate(request.get_ref())?;
+        log::info!(
+            "[SEARCH_USERS] Got a request from {:?}",
+            request.remote_addr()
+        );
+
+        let req = request.get_ref();
+        let sort_by = user_sort_field_from_proto(req.sort_by)?;
+
+        // Igual que en get_all_users se pide un usuario de mas para saber si hay otra pagina
+        let page_size = req.page_size.min(self.query_limit);
+        let mut query = if page_size > 0 {
+            GetUsersScheme::new().with_limit(page_size + 1)
+        } else {
+            GetUsersScheme::new().with_limit(self.query_limit)
+        }
+        .with_include_deleted(req.include_deleted)
+        .with_name_contains(non_empty(&req.name_contains))
+        .with_name_prefix(non_empty(&req.name_prefix))
+        .with_mail(non_empty(&req.mail).map(|mail| self.normalize_mail(&mail)))
+        .with_mail_domain(non_empty(&req.mail_domain))
+        .with_sort(sort_by, req.descending)
+        .with_text_query(non_empty(&req.query));
+        if query.text_query.is_some() && !query.is_ranked() {
+            return Err(ErrorKinsper::InvalidArgument(
+                "The query has no words to search".to_string(),
+            )
+            .into());
+        }
+        if !req.page_token.is_empty() {
+            query = with_search_page_token(query, &req.page_token)?;
+        }
+
+        // Una busqueda sin resultados devuelve el stream vacio
+        let mut users = self.db_context.stream_users(&query);
+        let user = match users.next().await {
+            Some(user) => user?,
+            None => return Ok(Response::new(ReceiverStream::new(mpsc::channel(1).1))),
+        };
+
+        Ok(Response::new(stream_page(
+            user,
+            users,
+            page_size,
+            self.stream_queue_size,
+            move |user| encode_search_page_token(sort_by, user),
+        )))
     }
 
     async fn create_user(
         &self,
         request: Request<CreateUserRequest>,
     ) -> Result<Response<CreateUserResponse>, Status> {
+        self.authorize(&request, Rpc::CreateUser)?;
+        self.validate(request.get_ref())?;
         let req = request.get_ref();
-        validate_mail(&req.mail)?;
+        let mail = self.normalize_mail(&req.mail);
 
         log::info!(
             "[CREATE_USER] Got a request from {:?}",
             request.remote_addr()
         );
 
+        // El id del cliente es opcional, si no viene lo genera el servidor
+        let id = match &req.id {
+            Some(id) if !id.id.is_empty() => id.id.clone(),
+            _ => self.id_format.generate(),
+        };
         let user = CreateUserScheme {
-            id: self.id_to_str(&req.id)?.to_string(),
+            id,
             name: req.name.clone(),
-            mail: req.mail.clone(),
+            mail,
         };
 
-        Ok(self
-            .db_context
-            .add_user(&user)
-            .await
-            .map(|_| Response::new(CreateUserResponse {}))?)
+        let created = self.notify_change(
+            self.db_context
+                .add_user(&user, &self.caller(&request))
+                .await,
+        )?;
+
+        Ok(Response::new(CreateUserResponse {
+            user: Some(created.into()),
+        }))
     }
 
     async fn update_name_user(
         &self,
         request: Request<UpdateUserNameRequest>,
     ) -> Result<Response<UpdateUserNameResponse>, Status> {
+        self.authorize(&request, Rpc::UpdateNameUser)?;
+        self.validate(request.get_ref())?;
         let req: &UpdateUserNameRequest = request.get_ref();
         let id = self.id_to_str(&req.id)?;
 
@@ -142,9 +623,16 @@ impl UserService for MyUserService {
         );
 
         let name = req.name.clone();
+        let expected_version = expected_version(req.expected_version)?;
         self.update_user_helper(
             id,
-            || UpdateUserSchema::new().with_name(name).finalize(),
+            &self.caller(&request),
+            || {
+                UpdateUserSchema::new()
+                    .with_name(name)
+                    .with_expected_version(expected_version)
+                    .finalize()
+            },
             || UpdateUserNameResponse {},
         )
         .await
@@ -154,19 +642,27 @@ impl UserService for MyUserService {
         &self,
         request: Request<UpdateUserMailRequest>,
     ) -> Result<Response<UpdateUserMailResponse>, Status> {
+        self.authorize(&request, Rpc::UpdateMailUser)?;
+        self.validate(request.get_ref())?;
         let req: &UpdateUserMailRequest = request.get_ref();
         let id = self.id_to_str(&req.id)?;
-        validate_mail(&req.mail)?;
+        let mail = self.normalize_mail(&req.mail);
 
         log::info!(
             "[UPDATE_USER_MAIL] Got a request from {:?}",
             request.remote_addr()
         );
 
-        let mail = req.mail.clone();
+        let expected_version = expected_version(req.expected_version)?;
         self.update_user_helper(
             id,
-            || UpdateUserSchema::new().with_mail(mail).finalize(),
+            &self.caller(&request),
+            || {
+                UpdateUserSchema::new()
+                    .with_mail(mail)
+                    .with_expected_version(expected_version)
+                    .finalize()
+            },
             || UpdateUserMailResponse {},
         )
         .await
@@ -176,7 +672,11 @@ impl UserService for MyUserService {
         &self,
         request: Request<DeleteUserRequest>,
     ) -> Result<Response<DeleteUserResponse>, Status> {
-        let id = self.id_to_str(&request.get_ref().id)?;
+        self.authorize(&request, Rpc::DeleteUser)?;
+        self.validate(request.get_ref())?;
+        let req = request.get_ref();
+        let id = self.id_to_str(&req.id)?;
+        let expected_version = expected_version(req.expected_version)?;
 
         log::info!(
             "[DELETE_USER] Got a request from {:?}",
@@ -184,26 +684,221 @@ impl UserService for MyUserService {
         );
 
         Ok(self
-            .db_context
-            .delete_user(id)
-            .await
+            .notify_change(
+                self.db_context
+                    .delete_user(id, expected_version, &self.caller(&request))
+                    .await,
+            )
             .map(|_| Response::new(DeleteUserResponse {}))?)
     }
 
+    async fn restore_user(
+        &self,
+        request: Request<RestoreUserRequest>,
+    ) -> Result<Response<RestoreUserResponse>, Status> {
+        self.authorize(&request, Rpc::RestoreUser)?;
+        self.validate(request.get_ref())?;
+        let id = self.id_to_str(&request.get_ref().id)?;
+
+        log::info!(
+            "[RESTORE_USER] Got a request from {:?}",
+            request.remote_addr()
+        );
+
+        Ok(self
+            .notify_change(
+                self.db_context
+                    .restore_user(id, &self.caller(&request))
+                    .await,
+            )
+            .map(|_| Response::new(RestoreUserResponse {}))?)
+    }
+
+    async fn purge_deleted_users(
+        &self,
+        request: Request<PurgeDeletedUsersRequest>,
+    ) -> Result<Response<PurgeDeletedUsersResponse>, Status> {
+        self.authorize(&request, Rpc::PurgeDeletedUsers)?;
+        self.validate(request.get_ref())?;
+        log::info!(
+            "[PURGE_DELETED_USERS] Got a request from {:?}",
+            request.remote_addr()
+        );
+
+        let retention_days = match request.get_ref().retention_days {
+            0 => PURGE_RETENTION_DAYS,
+            days => days,
+        };
+        let deleted_before = Utc::now() - Duration::days(retention_days.into());
+
+        Ok(self
+            .notify_change(
+                self.db_context
+                    .purge_deleted_users(deleted_before, &self.caller(&request))
+                    .await,
+            )
+            .map(|purged| Response::new(PurgeDeletedUsersResponse { purged }))?)
+    }
+
     async fn reset_user_table(
         &self,
         request: Request<ResetUserTableRequest>,
     ) -> Result<Response<ResetUserTableResponse>, Status> {
+        self.authorize(&request, Rpc::ResetUserTable)?;
+        if !self.environment.allows_reset() {
+            return Err(ErrorKinsper::ResetDisabled(format!(
+                "ResetUserTable is disabled in {} mode",
+                self.environment.as_str()
+            ))
+            .into());
+        }
+        // Sin nombre de base no hay confirmacion posible, ni siquiera la vacia
+        if self.database_name.is_empty() {
+            return Err(ErrorKinsper::ResetDisabled(
+                "ResetUserTable is disabled without a database name".to_string(),
+            )
+            .into());
+        }
+        self.validate(request.get_ref())?;
+        let req = request.get_ref();
+        if req.confirmation != self.database_name {
+            return Err(ErrorKinsper::InvalidRequest(vec![FieldViolation {
+                field: "confirmation".to_string(),
+                description: "Invalid confirmation: must be the database name".to_string(),
+            }])
+            .into());
+        }
         log::info!(
             "[RESET_USER_TABLE] Got a request from {:?}",
             request.remote_addr()
         );
 
+        let snapshot_table = req.snapshot.then(|| snapshot_table_name(Utc::now()));
         Ok(self
-            .db_context
-            .reset_table()
-            .await
-            .map(|_| Response::new(ResetUserTableResponse {}))?)
+            .notify_change(
+                self.db_context
+                    .reset_table(snapshot_table.as_deref(), &self.caller(&request))
+                    .await,
+            )
+            .map(|_| {
+                Response::new(ResetUserTableResponse {
+                    snapshot_table: snapshot_table.unwrap_or_default(),
+                })
+            })?)
+    }
+
+    async fn list_audit_events(
+        &self,
+        request: Request<ListAuditEventsRequest>,
+    ) -> Result<Response<ListAuditEventsResponse>, Status> {
+        self.authorize(&request, Rpc::ListAuditEvents)?;
+        self.validate(request.get_ref())?;
+        log::info!(
+            "[LIST_AUDIT_EVENTS] Got a request from {:?}",
+            request.remote_addr()
+        );
+
+        let req = request.get_ref();
+        let page_size = match req.page_size {
+            0 => self.query_limit,
+            page_size => page_size.min(self.query_limit),
+        };
+
+        // Igual que en get_all_users se pide un evento de mas para saber si hay otra pagina
+        let mut query = GetAuditEventsScheme::new()
+            .with_limit(page_size + 1)
+            .with_user_id(Some(req.user_id.clone()).filter(|user_id| !user_id.is_empty()))
+            .with_operation(audit_operation_from_proto(req.operation)?)
+            .with_created_after(from_optional_timestamp(&req.created_after)?)
+            .with_created_before(from_optional_timestamp(&req.created_before)?);
+        if !req.page_token.is_empty() {
+            let after_id = decode_page_token(&req.page_token)?
+                .parse::<i64>()
+                .map_err(|_| ErrorKinsper::InvalidPageToken("Invalid page token".to_string()))?;
+            query = query.with_after_id(after_id);
+        }
+
+        let mut events = self.db_context.list_audit_events(&query).await?;
+        let next_page_token = if events.len() > page_size as usize {
+            events.truncate(page_size as usize);
+            events
+                .last()
+                .map(|event| encode_page_token(&event.id.to_string()))
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        Ok(Response::new(ListAuditEventsResponse {
+            events: events.into_iter().map(AuditEvent::from).collect(),
+            next_page_token,
+        }))
+    }
+
+    type WatchUsersStream = ReceiverStream<Result<WatchUsersResponse, Status>>;
+
+    async fn watch_users(
+        &self,
+        request: Request<WatchUsersRequest>,
+    ) -> Result<Response<Self::WatchUsersStream>, Status> {
+        self.authorize(&request, Rpc::WatchUsers)?;
+        self.validate(request.get_ref())?;
+        log::info!(
+            "[WATCH_USERS] Got a request from {:?}",
+            request.remote_addr()
+        );
+
+        let req = request.get_ref();
+        // Se suscribe antes de leer el cursor para no perder avisos en el medio
+        let mut changes = self.changes.subscribe();
+        let mut cursor = match req.resume_token.as_str() {
+            "" => self.db_context.last_audit_event_id().await?.unwrap_or(0),
+            resume_token => decode_resume_token(resume_token)?,
+        };
+        let user_id = Some(req.user_id.clone()).filter(|user_id| !user_id.is_empty());
+
+        let db_context = Arc::clone(&self.db_context);
+        let query_limit = self.query_limit;
+        let (tx, rx) = mpsc::channel(self.stream_queue_size);
+
+        // Los cambios salen de la auditoria, asi al reconectar con el resume token se
+        // reciben los eventos que pasaron mientras el cliente estaba desconectado
+        tokio::spawn(async move {
+            loop {
+                let query = GetAuditEventsScheme::new()
+                    .with_limit(query_limit)
+                    .with_after_id(cursor)
+                    .with_user_id(user_id.clone());
+                let events = match db_context.list_audit_events(&query).await {
+                    Ok(events) => events,
+                    Err(err) => {
+                        let _ = tx.send(Err(err.into())).await;
+                        break;
+                    }
+                };
+
+                let caught_up = events.len() < query_limit as usize;
+                for event in events {
+                    cursor = event.id;
+                    let response = WatchUsersResponse::try_from(event).map_err(Status::from);
+                    if tx.send(response).await.is_err() {
+                        return;
+                    }
+                }
+
+                if caught_up {
+                    tokio::select! {
+                        changed = changes.changed() => if changed.is_err() { break },
+                        _ = tokio::time::sleep(std::time::Duration::from_millis(
+                            WATCH_POLL_INTERVAL_MS,
+                        )) => (),
+                        _ = tx.closed() => break,
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 }
 
@@ -220,22 +915,51 @@ mod test_tonic_server {
     use tonic::Request;
     use tower::service_fn;
 
-    use crate::data::context::Database;
-    use crate::data::handler::handler_tests::TEST_COUNTER;
-    use crate::errors::ErrorKinsper;
+    use crate::auth::{Authenticator, AUTHORIZATION_METADATA_KEY};
+    use crate::authorization::AuthorizationPolicy;
+    use crate::config::ServerSettings;
+    use crate::data::memory::InMemoryDatabase;
+    use crate::errors::{bad_request_details, ErrorDetails};
     use crate::handler_server::MyUserService;
+    use crate::validation::PAGE_TOKEN_MAX_LENGTH;
+    use crate::ServerEnvironment;
+    use prost_types::Timestamp;
     use user_service::{
-        user_service_client::UserServiceClient, CreateUserRequest, GetAllUserRequest,
-        GetUserRequest, ResetUserTableRequest, UpdateUserMailRequest, UpdateUserNameRequest,
-        UserId,
+        user_service_client::UserServiceClient, AuditOperation, CreateUserRequest,
+        DeleteUserRequest, GetAllUserRequest, GetUserByMailRequest, GetUserRequest,
+        ListAuditEventsRequest, ResetUserTableRequest, RestoreUserRequest, SearchUsersRequest,
+        UpdateUserMailRequest, UpdateUserNameRequest, UserId, UserSortField, WatchUsersRequest,
     };
     pub mod user_service {
         tonic::include_proto!("user_service");
     }
-    use std::sync::atomic::Ordering;
 
-    // https://stackoverflow.com/questions/69845664/how-to-integration-test-tonic-application
+    // Cada test levanta su propio store en memoria, sin depender de MySQL
     async fn server_and_client_stub() -> (impl Future<Output = ()>, UserServiceClient<Channel>) {
+        configured_server_and_client_stub(
+            MyUserService::new(InMemoryDatabase::new()),
+            Authenticator::new(),
+        )
+        .await
+    }
+
+    async fn authenticated_server_and_client_stub(
+        authenticator: Authenticator,
+        authorization_policy: AuthorizationPolicy,
+    ) -> (impl Future<Output = ()>, UserServiceClient<Channel>) {
+        configured_server_and_client_stub(
+            MyUserService::new(InMemoryDatabase::new())
+                .with_authorization_policy(authorization_policy),
+            authenticator,
+        )
+        .await
+    }
+
+    // https://stackoverflow.com/questions/69845664/how-to-integration-test-tonic-application
+    async fn configured_server_and_client_stub(
+        user_service: MyUserService<InMemoryDatabase>,
+        authenticator: Authenticator,
+    ) -> (impl Future<Output = ()>, UserServiceClient<Channel>) {
         let socket = NamedTempFile::new().unwrap();
         let socket = Arc::new(socket.into_temp_path());
         std::fs::remove_file(&*socket).unwrap();
@@ -243,15 +967,12 @@ mod test_tonic_server {
         let uds = UnixListener::bind(&*socket).unwrap();
         let stream = UnixListenerStream::new(uds);
 
-        let database_url = std::env::var("DATABASE_URL")
-            .map_err(|_| ErrorKinsper::InvalidUri("Invalid database url".to_string()))
-            .unwrap();
-        let db_context = Database::connect(&database_url).await.unwrap();
-        db_context.create_table().await.unwrap();
-
         let serve_future = async {
             let result = Server::builder()
-                .add_service(UserServiceServer::new(MyUserService { db_context }))
+                .add_service(UserServiceServer::with_interceptor(
+                    user_service,
+                    authenticator,
+                ))
                 .serve_with_incoming(stream)
                 .await;
             // Server must be running fine...
@@ -275,17 +996,6 @@ mod test_tonic_server {
         (serve_future, client)
     }
 
-    async fn teardown(mut client: UserServiceClient<Channel>) -> sqlx::Result<()> {
-        if TEST_COUNTER.fetch_sub(1, Ordering::SeqCst) == 1 {
-            println!("Dropping table!");
-            client
-                .reset_user_table(Request::new(ResetUserTableRequest {}))
-                .await
-                .unwrap();
-        }
-        Ok(())
-    }
-
     #[tokio::test]
     async fn test_01_create_user_valid_is_ok() {
         let (serve_future, mut client) = server_and_client_stub().await;
@@ -301,7 +1011,6 @@ mod test_tonic_server {
                 }))
                 .await;
             assert!(response.is_ok());
-            teardown(client).await.unwrap();
         };
 
         tokio::select! {
@@ -325,7 +1034,6 @@ mod test_tonic_server {
                 }))
                 .await;
             assert!(response.is_err());
-            teardown(client).await.unwrap();
         };
 
         tokio::select! {
@@ -347,7 +1055,6 @@ mod test_tonic_server {
                 }))
                 .await;
             assert!(response.is_err());
-            teardown(client).await.unwrap();
         };
 
         tokio::select! {
@@ -380,7 +1087,6 @@ mod test_tonic_server {
                 .await;
             assert!(response.is_ok());
             assert_eq!(response.unwrap().into_inner().name, "name");
-            teardown(client).await.unwrap();
         };
 
         tokio::select! {
@@ -415,11 +1121,12 @@ mod test_tonic_server {
                 .await;
 
             let response = client
-                .get_all_users(Request::new(GetAllUserRequest { limit: 2 }))
+                .get_all_users(Request::new(GetAllUserRequest {
+                    limit: 2,
+                    ..Default::default()
+                }))
                 .await;
             assert!(response.is_ok());
-
-            teardown(client).await.unwrap();
         };
 
         tokio::select! {
@@ -449,6 +1156,7 @@ mod test_tonic_server {
                         id: "test06_id".to_string(),
                     }),
                     name: "name_updated".to_string(),
+                    ..Default::default()
                 }))
                 .await;
 
@@ -462,8 +1170,6 @@ mod test_tonic_server {
                 .await;
             assert!(response_get.is_ok());
             assert_eq!(response_get.unwrap().into_inner().name, "name_updated");
-
-            teardown(client).await.unwrap();
         };
 
         tokio::select! {
@@ -493,6 +1199,7 @@ mod test_tonic_server {
                         id: "test07_id".to_string(),
                     }),
                     mail: "mailnew@mail.com".to_string(),
+                    ..Default::default()
                 }))
                 .await;
 
@@ -507,7 +1214,6 @@ mod test_tonic_server {
             assert!(response.is_ok());
             assert!(response_get.is_ok());
             assert_eq!(response_get.unwrap().into_inner().mail, "mailnew@mail.com");
-            teardown(client).await.unwrap();
         };
 
         tokio::select! {
@@ -515,4 +1221,1214 @@ mod test_tonic_server {
             _ = request_future => (),
         }
     }
+
+    #[tokio::test]
+    async fn test_08_get_all_users_with_page_size_walks_all_users_in_order() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            for id in ["test08_e", "test08_a", "test08_d", "test08_b", "test08_c"] {
+                let _ = client
+                    .create_user(Request::new(CreateUserRequest {
+                        id: Some(UserId { id: id.to_string() }),
+                        name: "name".to_string(),
+                        mail: format!("{}@name.com", id),
+                    }))
+                    .await;
+            }
+
+            let mut ids = vec![];
+            let mut pages = 0;
+            let mut page_token = String::new();
+            loop {
+                let mut stream = client
+                    .get_all_users(Request::new(GetAllUserRequest {
+                        page_size: 2,
+                        page_token: page_token.clone(),
+                        ..Default::default()
+                    }))
+                    .await
+                    .unwrap()
+                    .into_inner();
+                pages += 1;
+                page_token = String::new();
+                while let Some(user) = stream.message().await.unwrap() {
+                    ids.push(user.id.unwrap().id);
+                    page_token = user.next_page_token;
+                }
+                if page_token.is_empty() {
+                    break;
+                }
+            }
+
+            assert_eq!(pages, 3);
+            assert_eq!(
+                ids,
+                vec!["test08_a", "test08_b", "test08_c", "test08_d", "test08_e"]
+            );
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_09_get_all_users_with_invalid_page_token_is_err() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let response = client
+                .get_all_users(Request::new(GetAllUserRequest {
+                    page_size: 2,
+                    page_token: "not-a-token".to_string(),
+                    ..Default::default()
+                }))
+                .await;
+
+            assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_10_delete_and_restore_user_is_ok() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let user_id = || {
+                Some(UserId {
+                    id: "test10_id".to_string(),
+                })
+            };
+            let _ = client
+                .create_user(Request::new(CreateUserRequest {
+                    id: user_id(),
+                    name: "name".to_string(),
+                    mail: "name@name.com".to_string(),
+                }))
+                .await;
+
+            let response = client
+                .delete_user(Request::new(DeleteUserRequest {
+                    id: user_id(),
+                    ..Default::default()
+                }))
+                .await;
+            assert!(response.is_ok());
+
+            let response_get = client
+                .get_user(Request::new(GetUserRequest { id: user_id() }))
+                .await;
+            assert_eq!(response_get.unwrap_err().code(), tonic::Code::NotFound);
+
+            let mut stream = client
+                .get_all_users(Request::new(GetAllUserRequest {
+                    limit: 10,
+                    include_deleted: true,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(stream.message().await.unwrap().unwrap().deleted);
+
+            let response = client
+                .restore_user(Request::new(RestoreUserRequest { id: user_id() }))
+                .await;
+            assert!(response.is_ok());
+
+            let response_get = client
+                .get_user(Request::new(GetUserRequest { id: user_id() }))
+                .await;
+            assert!(response_get.is_ok());
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_11_get_user_returns_timestamps_and_filters_by_them() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let _ = client
+                .create_user(Request::new(CreateUserRequest {
+                    id: Some(UserId {
+                        id: "test11_id".to_string(),
+                    }),
+                    name: "name".to_string(),
+                    mail: "name@name.com".to_string(),
+                }))
+                .await;
+
+            let user = client
+                .get_user(Request::new(GetUserRequest {
+                    id: Some(UserId {
+                        id: "test11_id".to_string(),
+                    }),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            let created_at = user.created_at.unwrap();
+            assert_eq!(Some(created_at.clone()), user.updated_at);
+
+            let response = client
+                .get_all_users(Request::new(GetAllUserRequest {
+                    limit: 10,
+                    created_before: Some(created_at),
+                    ..Default::default()
+                }))
+                .await;
+            assert_eq!(response.unwrap_err().code(), tonic::Code::NotFound);
+
+            let response = client
+                .get_all_users(Request::new(GetAllUserRequest {
+                    limit: 10,
+                    updated_after: Some(Timestamp {
+                        seconds: 0,
+                        nanos: -1,
+                    }),
+                    ..Default::default()
+                }))
+                .await;
+            assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_12_update_user_with_stale_version_is_aborted() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let user_id = || {
+                Some(UserId {
+                    id: "test12_id".to_string(),
+                })
+            };
+            let _ = client
+                .create_user(Request::new(CreateUserRequest {
+                    id: user_id(),
+                    name: "name".to_string(),
+                    mail: "name@name.com".to_string(),
+                }))
+                .await;
+            let version = client
+                .get_user(Request::new(GetUserRequest { id: user_id() }))
+                .await
+                .unwrap()
+                .into_inner()
+                .version;
+
+            let update = |name: &str| UpdateUserNameRequest {
+                id: user_id(),
+                name: name.to_string(),
+                expected_version: version,
+            };
+            let response = client.update_name_user(Request::new(update("first"))).await;
+            assert!(response.is_ok());
+            let response = client
+                .update_name_user(Request::new(update("second")))
+                .await;
+            assert_eq!(response.unwrap_err().code(), tonic::Code::Aborted);
+
+            let user = client
+                .get_user(Request::new(GetUserRequest { id: user_id() }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(user.name, "first");
+            assert_eq!(user.version, version + 1);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_13_list_audit_events_records_caller_and_paginates() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let user_id = || {
+                Some(UserId {
+                    id: "test13_id".to_string(),
+                })
+            };
+            let mut request = Request::new(CreateUserRequest {
+                id: user_id(),
+                name: "name".to_string(),
+                mail: "name@name.com".to_string(),
+            });
+            request
+                .metadata_mut()
+                .insert(crate::CALLER_METADATA_KEY, "alice".parse().unwrap());
+            assert!(client.create_user(request).await.is_ok());
+            for name in ["first", "second"] {
+                let response = client
+                    .update_name_user(Request::new(UpdateUserNameRequest {
+                        id: user_id(),
+                        name: name.to_string(),
+                        expected_version: 0,
+                    }))
+                    .await;
+                assert!(response.is_ok());
+            }
+
+            let list = |page_token: String| ListAuditEventsRequest {
+                user_id: "test13_id".to_string(),
+                page_size: 2,
+                page_token,
+                ..Default::default()
+            };
+            let first_page = client
+                .list_audit_events(Request::new(list(String::new())))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(first_page.events.len(), 2);
+            assert_eq!(first_page.events[0].operation(), AuditOperation::Create);
+            assert_eq!(first_page.events[0].caller, "alice");
+            assert!(first_page.events[0].before.is_empty());
+            assert!(first_page.events[1].after.contains("first"));
+            assert!(!first_page.next_page_token.is_empty());
+
+            let second_page = client
+                .list_audit_events(Request::new(list(first_page.next_page_token)))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(second_page.events.len(), 1);
+            assert!(second_page.events[0].before.contains("first"));
+            assert!(second_page.events[0].after.contains("second"));
+            assert!(second_page.next_page_token.is_empty());
+
+            let updates = client
+                .list_audit_events(Request::new(ListAuditEventsRequest {
+                    operation: AuditOperation::Update.into(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(updates.events.len(), 2);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_14_watch_users_streams_changes_and_resumes_from_token() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let user_id = |id: &str| Some(UserId { id: id.to_string() });
+            let create = |id: &str| CreateUserRequest {
+                id: user_id(id),
+                name: "name".to_string(),
+                mail: format!("{}@name.com", id),
+            };
+            // Los cambios previos a la suscripcion no se envian sin resume token
+            assert!(client
+                .create_user(Request::new(create("old")))
+                .await
+                .is_ok());
+
+            let mut watcher = client.clone();
+            let mut stream = watcher
+                .watch_users(Request::new(WatchUsersRequest::default()))
+                .await
+                .unwrap()
+                .into_inner();
+
+            assert!(client
+                .create_user(Request::new(create("new")))
+                .await
+                .is_ok());
+            let created = stream.message().await.unwrap().unwrap();
+            assert_eq!(created.operation(), AuditOperation::Create);
+            assert_eq!(created.user_id, "new");
+            assert_eq!(created.user.as_ref().unwrap().version, 1);
+
+            let response = client
+                .update_name_user(Request::new(UpdateUserNameRequest {
+                    id: user_id("new"),
+                    name: "renamed".to_string(),
+                    expected_version: 0,
+                }))
+                .await;
+            assert!(response.is_ok());
+            let response = client
+                .delete_user(Request::new(DeleteUserRequest {
+                    id: user_id("new"),
+                    expected_version: 0,
+                }))
+                .await;
+            assert!(response.is_ok());
+
+            let updated = stream.message().await.unwrap().unwrap();
+            assert_eq!(updated.operation(), AuditOperation::Update);
+            assert_eq!(updated.user.unwrap().name, "renamed");
+            let deleted = stream.message().await.unwrap().unwrap();
+            assert_eq!(deleted.operation(), AuditOperation::Delete);
+            assert!(deleted.user.unwrap().deleted);
+            drop(stream);
+
+            // Al reconectar con el token del primer evento se reciben los siguientes
+            let mut stream = client
+                .watch_users(Request::new(WatchUsersRequest {
+                    resume_token: created.resume_token,
+                    user_id: "new".to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            let resumed = stream.message().await.unwrap().unwrap();
+            assert_eq!(resumed.resume_token, updated.resume_token);
+            let resumed = stream.message().await.unwrap().unwrap();
+            assert_eq!(resumed.resume_token, deleted.resume_token);
+
+            let response = client
+                .watch_users(Request::new(WatchUsersRequest {
+                    resume_token: "zz".to_string(),
+                    ..Default::default()
+                }))
+                .await;
+            assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_15_create_user_with_same_mail_in_other_case_is_already_exists() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let create = |id: &str, mail: &str| CreateUserRequest {
+                id: Some(UserId { id: id.to_string() }),
+                name: "name".to_string(),
+                mail: mail.to_string(),
+            };
+            let response = client
+                .create_user(Request::new(create("test15_a", " Jhon@Mail.COM ")))
+                .await;
+            assert!(response.is_ok());
+
+            let user = client
+                .get_user(Request::new(GetUserRequest {
+                    id: Some(UserId {
+                        id: "test15_a".to_string(),
+                    }),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(user.mail, "jhon@mail.com");
+
+            let status = client
+                .create_user(Request::new(create("test15_b", "jhon@mail.com")))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::AlreadyExists);
+            assert!(status.message().contains("mail"));
+
+            let status = client
+                .create_user(Request::new(create("test15_a", "other@mail.com")))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::AlreadyExists);
+            assert!(status.message().contains("id"));
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_16_get_user_by_mail_normalizes_and_validates_the_mail() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let response = client
+                .create_user(Request::new(CreateUserRequest {
+                    id: Some(UserId {
+                        id: "test16_id".to_string(),
+                    }),
+                    name: "name".to_string(),
+                    mail: "test16@name.com".to_string(),
+                }))
+                .await;
+            assert!(response.is_ok());
+
+            let get_by_mail = |mail: &str| {
+                Request::new(GetUserByMailRequest {
+                    mail: mail.to_string(),
+                })
+            };
+            let user = client
+                .get_user_by_mail(get_by_mail(" Test16@NAME.com"))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(user.id.unwrap().id, "test16_id");
+
+            let status = client
+                .get_user_by_mail(get_by_mail("other@name.com"))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::NotFound);
+
+            let status = client
+                .get_user_by_mail(get_by_mail("not-a-mail"))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_17_search_users_filters_sorts_and_paginates() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            for (id, name, mail) in [
+                ("test17_a", "Zoe Doe", "zoe@doe.com"),
+                ("test17_b", "Ana Doe", "ana@doe.com"),
+                ("test17_c", "Ana Doe", "ana2@doe.com"),
+                ("test17_d", "Bob Smith", "bob@smith.com"),
+            ] {
+                let response = client
+                    .create_user(Request::new(CreateUserRequest {
+                        id: Some(UserId { id: id.to_string() }),
+                        name: name.to_string(),
+                        mail: mail.to_string(),
+                    }))
+                    .await;
+                assert!(response.is_ok());
+            }
+
+            let mut ids = vec![];
+            let mut page_token = String::new();
+            loop {
+                let mut stream = client
+                    .search_users(Request::new(SearchUsersRequest {
+                        name_contains: "doe".to_string(),
+                        sort_by: UserSortField::Name.into(),
+                        page_size: 2,
+                        page_token: page_token.clone(),
+                        ..Default::default()
+                    }))
+                    .await
+                    .unwrap()
+                    .into_inner();
+                page_token = String::new();
+                while let Some(user) = stream.message().await.unwrap() {
+                    ids.push(user.id.unwrap().id);
+                    page_token = user.next_page_token;
+                }
+                if page_token.is_empty() {
+                    break;
+                }
+            }
+            assert_eq!(ids, vec!["test17_b", "test17_c", "test17_a"]);
+
+            let mut stream = client
+                .search_users(Request::new(SearchUsersRequest {
+                    mail_domain: "Smith.com".to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            let user = stream.message().await.unwrap().unwrap();
+            assert_eq!(user.id.unwrap().id, "test17_d");
+            assert!(stream.message().await.unwrap().is_none());
+
+            let mut stream = client
+                .search_users(Request::new(SearchUsersRequest {
+                    name_prefix: "nobody".to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(stream.message().await.unwrap().is_none());
+
+            let status = client
+                .search_users(Request::new(SearchUsersRequest {
+                    page_token: "zz".to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_18_search_users_with_query_returns_scores_by_relevance() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            for (id, name) in [
+                ("test18_a", "Johnny Doe"),
+                ("test18_b", "John Doe"),
+                ("test18_c", "Jane Roe"),
+            ] {
+                let response = client
+                    .create_user(Request::new(CreateUserRequest {
+                        id: Some(UserId { id: id.to_string() }),
+                        name: name.to_string(),
+                        mail: format!("{}@name.com", id),
+                    }))
+                    .await;
+                assert!(response.is_ok());
+            }
+
+            let search = |query: &str, page_token: String| SearchUsersRequest {
+                query: query.to_string(),
+                page_size: 1,
+                page_token,
+                ..Default::default()
+            };
+            let mut stream = client
+                .search_users(Request::new(search("john do", String::new())))
+                .await
+                .unwrap()
+                .into_inner();
+            let first = stream.message().await.unwrap().unwrap();
+            assert_eq!(first.id.unwrap().id, "test18_b");
+            assert!(first.score > 0.0);
+            assert!(!first.next_page_token.is_empty());
+
+            let mut stream = client
+                .search_users(Request::new(search("john do", first.next_page_token)))
+                .await
+                .unwrap()
+                .into_inner();
+            let second = stream.message().await.unwrap().unwrap();
+            assert_eq!(second.id.unwrap().id, "test18_a");
+            assert!(second.score < first.score);
+            assert!(second.next_page_token.is_empty());
+
+            let status = client
+                .search_users(Request::new(search("+*", String::new())))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_19_create_user_without_id_returns_user_with_generated_id() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let create = |id: Option<&str>, mail: &str| {
+                Request::new(CreateUserRequest {
+                    id: id.map(|id| UserId { id: id.to_string() }),
+                    name: "name".to_string(),
+                    mail: mail.to_string(),
+                })
+            };
+
+            let created = client
+                .create_user(create(None, "test19_a@name.com"))
+                .await
+                .unwrap()
+                .into_inner()
+                .user
+                .unwrap();
+            let id = created.id.unwrap().id;
+            assert_eq!(uuid::Uuid::parse_str(&id).unwrap().get_version_num(), 7);
+            assert_eq!(created.mail, "test19_a@name.com");
+            assert_eq!(created.version, 1);
+            assert!(created.created_at.is_some());
+
+            let user = client
+                .get_user(Request::new(GetUserRequest {
+                    id: Some(UserId { id: id.clone() }),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(user.mail, "test19_a@name.com");
+
+            // Un id vacio tambien lo genera el servidor
+            let created = client
+                .create_user(create(Some(""), "test19_b@name.com"))
+                .await
+                .unwrap()
+                .into_inner()
+                .user
+                .unwrap();
+            assert_ne!(created.id.unwrap().id, id);
+
+            let created = client
+                .create_user(create(Some("test19_id"), "test19_c@name.com"))
+                .await
+                .unwrap()
+                .into_inner()
+                .user
+                .unwrap();
+            assert_eq!(created.id.unwrap().id, "test19_id");
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_20_requests_with_invalid_id_are_invalid_argument() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let too_long = "a".repeat(49);
+            for id in ["", "with space", "semi;colon", too_long.as_str()] {
+                let status = client
+                    .get_user(Request::new(GetUserRequest {
+                        id: Some(UserId { id: id.to_string() }),
+                    }))
+                    .await
+                    .unwrap_err();
+                assert_eq!(status.code(), tonic::Code::InvalidArgument);
+                assert!(status.message().starts_with("Invalid id: "));
+
+                let status = client
+                    .delete_user(Request::new(DeleteUserRequest {
+                        id: Some(UserId { id: id.to_string() }),
+                        ..Default::default()
+                    }))
+                    .await
+                    .unwrap_err();
+                assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            }
+
+            let status = client
+                .create_user(Request::new(CreateUserRequest {
+                    id: Some(UserId { id: too_long }),
+                    name: "name".to_string(),
+                    mail: "test20@name.com".to_string(),
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert_eq!(
+                status.message(),
+                "Invalid id: must be at most 48 characters long"
+            );
+
+            let status = client
+                .update_name_user(Request::new(UpdateUserNameRequest {
+                    id: None,
+                    name: "name".to_string(),
+                    expected_version: 0,
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_21_invalid_request_returns_bad_request_with_field_violations() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let status = client
+                .create_user(Request::new(CreateUserRequest {
+                    id: Some(UserId {
+                        id: "test21 id".to_string(),
+                    }),
+                    name: " ".to_string(),
+                    mail: "test21".to_string(),
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+            let bad_request = bad_request_details(&status).unwrap();
+            let fields: Vec<&str> = bad_request
+                .field_violations
+                .iter()
+                .map(|violation| violation.field.as_str())
+                .collect();
+            assert_eq!(fields, vec!["id", "name", "mail"]);
+            assert_eq!(
+                bad_request.field_violations[1].description,
+                "Invalid name: must not be empty"
+            );
+
+            let status = client
+                .update_name_user(Request::new(UpdateUserNameRequest {
+                    id: Some(UserId {
+                        id: "test21_id".to_string(),
+                    }),
+                    name: "a".repeat(257),
+                    expected_version: 0,
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert_eq!(
+                status.message(),
+                "Invalid name: must be at most 256 characters long"
+            );
+            assert_eq!(
+                bad_request_details(&status).unwrap().field_violations.len(),
+                1
+            );
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_22_errors_have_error_info_with_stable_reason() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            let status = client
+                .get_user(Request::new(GetUserRequest {
+                    id: Some(UserId {
+                        id: "test22_id".to_string(),
+                    }),
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::NotFound);
+            let details = ErrorDetails::from_status(&status);
+            assert_eq!(details.reason(), Some("USER_NOT_FOUND"));
+            assert_eq!(details.retry_delay(), None);
+
+            let status = client
+                .get_user(Request::new(GetUserRequest { id: None }))
+                .await
+                .unwrap_err();
+            let details = ErrorDetails::from_status(&status);
+            assert_eq!(details.reason(), Some("INVALID_REQUEST"));
+            assert_eq!(details.bad_request.unwrap().field_violations.len(), 1);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_23_authentication_rejects_missing_tokens_and_audits_the_token_identity() {
+        let authenticator =
+            Authenticator::new().with_api_key("admin:s3cret:admin".parse().unwrap());
+        let (serve_future, mut client) =
+            authenticated_server_and_client_stub(authenticator, AuthorizationPolicy::new()).await;
+
+        let request_future = async {
+            let create = |token: Option<&str>| {
+                let mut request = Request::new(CreateUserRequest {
+                    id: Some(UserId {
+                        id: "test23_id".to_string(),
+                    }),
+                    name: "name".to_string(),
+                    mail: "test23@name.com".to_string(),
+                });
+                request
+                    .metadata_mut()
+                    .insert(crate::CALLER_METADATA_KEY, "mallory".parse().unwrap());
+                if let Some(token) = token {
+                    request.metadata_mut().insert(
+                        AUTHORIZATION_METADATA_KEY,
+                        format!("Bearer {}", token).parse().unwrap(),
+                    );
+                }
+                request
+            };
+
+            for token in [None, Some("wrong")] {
+                let status = client.create_user(create(token)).await.unwrap_err();
+                assert_eq!(status.code(), tonic::Code::Unauthenticated);
+                assert_eq!(
+                    ErrorDetails::from_status(&status).reason(),
+                    Some("UNAUTHENTICATED")
+                );
+            }
+            assert!(client.create_user(create(Some("s3cret"))).await.is_ok());
+
+            let mut request = Request::new(ListAuditEventsRequest::default());
+            request
+                .metadata_mut()
+                .insert(AUTHORIZATION_METADATA_KEY, "Bearer s3cret".parse().unwrap());
+            let events = client
+                .list_audit_events(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .events;
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].caller, "admin");
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_24_rpcs_not_allowed_for_the_caller_roles_are_permission_denied() {
+        let authenticator = Authenticator::new()
+            .with_api_key("viewer:viewer-key".parse().unwrap())
+            .with_api_key("root:root-key".parse().unwrap());
+        let authorization_policy = AuthorizationPolicy::new()
+            .with_subject_roles("viewer", &["reader".to_string()])
+            .unwrap()
+            .with_subject_roles("root", &["admin".to_string()])
+            .unwrap();
+        let (serve_future, mut client) =
+            authenticated_server_and_client_stub(authenticator, authorization_policy).await;
+
+        let request_future = async {
+            fn with_token<T>(token: &str, mut request: Request<T>) -> Request<T> {
+                request.metadata_mut().insert(
+                    AUTHORIZATION_METADATA_KEY,
+                    format!("Bearer {}", token).parse().unwrap(),
+                );
+                request
+            }
+            let create = || {
+                Request::new(CreateUserRequest {
+                    id: Some(UserId {
+                        id: "test24_id".to_string(),
+                    }),
+                    name: "name".to_string(),
+                    mail: "test24@name.com".to_string(),
+                })
+            };
+
+            let status = client
+                .create_user(with_token("viewer-key", create()))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+            assert_eq!(
+                ErrorDetails::from_status(&status).reason(),
+                Some("PERMISSION_DENIED")
+            );
+            assert!(client
+                .create_user(with_token("root-key", create()))
+                .await
+                .is_ok());
+
+            let get = || {
+                Request::new(GetUserRequest {
+                    id: Some(UserId {
+                        id: "test24_id".to_string(),
+                    }),
+                })
+            };
+            assert!(client
+                .get_user(with_token("viewer-key", get()))
+                .await
+                .is_ok());
+
+            // La autorizacion va antes que la validacion del pedido
+            let status = client
+                .create_user(with_token(
+                    "viewer-key",
+                    Request::new(CreateUserRequest::default()),
+                ))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_25_reset_user_table_requires_test_mode_and_the_database_name() {
+        let (production_serve_future, mut production_client) = server_and_client_stub().await;
+        let (unnamed_serve_future, mut unnamed_client) = configured_server_and_client_stub(
+            MyUserService::new(InMemoryDatabase::new()).with_environment(ServerEnvironment::Test),
+            Authenticator::new(),
+        )
+        .await;
+        let (serve_future, mut client) = configured_server_and_client_stub(
+            MyUserService::new(InMemoryDatabase::new())
+                .with_environment(ServerEnvironment::Test)
+                .with_database_name("users"),
+            Authenticator::new(),
+        )
+        .await;
+
+        let request_future = async {
+            let reset = |confirmation: &str| {
+                Request::new(ResetUserTableRequest {
+                    confirmation: confirmation.to_string(),
+                    snapshot: true,
+                })
+            };
+
+            let status = production_client
+                .reset_user_table(reset("users"))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+            assert_eq!(
+                ErrorDetails::from_status(&status).reason(),
+                Some("RESET_DISABLED")
+            );
+            let status = unnamed_client
+                .reset_user_table(reset(""))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+            let create = client
+                .create_user(Request::new(CreateUserRequest {
+                    id: Some(UserId {
+                        id: "test25_id".to_string(),
+                    }),
+                    name: "name".to_string(),
+                    mail: "test25@name.com".to_string(),
+                }))
+                .await;
+            assert!(create.is_ok());
+
+            for confirmation in ["", "other_db"] {
+                let status = client
+                    .reset_user_table(reset(confirmation))
+                    .await
+                    .unwrap_err();
+                assert_eq!(status.code(), tonic::Code::InvalidArgument);
+                assert_eq!(
+                    bad_request_details(&status).unwrap().field_violations[0].field,
+                    "confirmation"
+                );
+            }
+            assert!(client
+                .get_user(Request::new(GetUserRequest {
+                    id: Some(UserId {
+                        id: "test25_id".to_string(),
+                    }),
+                }))
+                .await
+                .is_ok());
+
+            let response = client
+                .reset_user_table(reset("users"))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(response.snapshot_table.starts_with("users_snapshot_"));
+            let status = client
+                .get_user(Request::new(GetUserRequest {
+                    id: Some(UserId {
+                        id: "test25_id".to_string(),
+                    }),
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::NotFound);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = production_serve_future => panic!("server returned first"),
+            _ = unnamed_serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_26_api_key_from_the_config_gets_the_roles_it_was_configured_with() {
+        let config = ServerSettings {
+            database_url: Some("sqlite::memory:".to_string()),
+            api_keys: Some(vec![
+                "admin:s3cret:admin".to_string(),
+                "viewer:viewer-key:reader".to_string(),
+            ]),
+            ..Default::default()
+        }
+        .resolve()
+        .unwrap();
+        let (serve_future, mut client) = authenticated_server_and_client_stub(
+            config.authenticator().unwrap(),
+            config.authorization_policy().unwrap(),
+        )
+        .await;
+
+        let request_future = async {
+            let create = |token: &str| {
+                let mut request = Request::new(CreateUserRequest {
+                    id: None,
+                    name: "name".to_string(),
+                    mail: "test26@name.com".to_string(),
+                });
+                request.metadata_mut().insert(
+                    AUTHORIZATION_METADATA_KEY,
+                    format!("Bearer {}", token).parse().unwrap(),
+                );
+                request
+            };
+
+            let status = client.create_user(create("viewer-key")).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+            assert!(client.create_user(create("s3cret")).await.is_ok());
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_27_search_users_paginates_names_and_ids_of_the_maximum_length() {
+        let (serve_future, mut client) = server_and_client_stub().await;
+
+        let request_future = async {
+            // Caracteres de 4 bytes en UTF-8, el peor caso del page token
+            let long_name = |last: char| format!("{}{}", "𝒜".repeat(255), last);
+            let long_id = |last: char| format!("{}{}", "i".repeat(47), last);
+            for last in ['c', 'a', 'b'] {
+                let response = client
+                    .create_user(Request::new(CreateUserRequest {
+                        id: Some(UserId { id: long_id(last) }),
+                        name: long_name(last),
+                        mail: format!("test27{}@name.com", last),
+                    }))
+                    .await;
+                assert!(response.is_ok());
+            }
+
+            let mut ids = vec![];
+            let mut page_token = String::new();
+            loop {
+                let mut stream = client
+                    .search_users(Request::new(SearchUsersRequest {
+                        name_prefix: "𝒜".to_string(),
+                        sort_by: UserSortField::Name.into(),
+                        page_size: 1,
+                        page_token: page_token.clone(),
+                        ..Default::default()
+                    }))
+                    .await
+                    .unwrap()
+                    .into_inner();
+                page_token = String::new();
+                while let Some(user) = stream.message().await.unwrap() {
+                    ids.push(user.id.unwrap().id);
+                    page_token = user.next_page_token;
+                }
+                if page_token.is_empty() {
+                    break;
+                }
+            }
+            assert_eq!(ids, vec![long_id('a'), long_id('b'), long_id('c')]);
+
+            let status = client
+                .search_users(Request::new(SearchUsersRequest {
+                    page_token: "0".repeat(PAGE_TOKEN_MAX_LENGTH + 2),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        };
+
+        tokio::select! {
+            _ = serve_future => panic!("server returned first"),
+            _ = request_future => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_28_stream_page_sends_the_error_read_after_the_last_row_of_the_page() {
+        use crate::data::model::UserModel;
+        use crate::errors::ErrorKinsper;
+        use chrono::Utc;
+        use futures::StreamExt;
+
+        let user = |id: &str| UserModel {
+            id: id.to_string(),
+            name: "Fede".to_string(),
+            mail: format!("fede{}@gmail.com", id),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            version: 1,
+            deleted_at: None,
+            score: None,
+        };
+        // La pagina es de 2 usuarios y falla la lectura del tercero
+        let users = futures::stream::iter(vec![
+            Ok(user("2")),
+            Err(ErrorKinsper::ConnectionError("connection lost".to_string())),
+        ])
+        .boxed();
+
+        let responses: Vec<_> = super::stream_page(user("1"), users, 2, 4, |user| user.id.clone())
+            .collect()
+            .await;
+        assert_eq!(responses.len(), 3);
+        assert!(responses[..2].iter().all(|response| response
+            .as_ref()
+            .unwrap()
+            .next_page_token
+            .is_empty()));
+        assert!(responses[2].is_err());
+    }
 }
@@ -1,40 +1,424 @@
+pub mod auth;
+pub mod authorization;
+pub mod config;
+pub mod connection;
 pub mod data;
 pub mod errors;
 pub mod handler_server;
+pub mod tls;
+pub mod validation;
 
 use std::env;
+use std::str::FromStr;
 
 use errors::ErrorKinsper;
-use tonic::Status;
 
-const DEFAULT_LEVEL_LOG: log::LevelFilter = log::LevelFilter::Info;
+pub const DEFAULT_LEVEL_LOG: log::LevelFilter = log::LevelFilter::Info;
 pub const SERVER_LOCALPORT: u16 = 50051;
 pub const SERVER_LOCALHOST: &str = "127.0.0.1";
 pub const QUERY_LIMIT_CLIENT: &str = "1024";
 pub const LIMIT_STREAM_QUEUE: usize = 1024;
+pub const PURGE_RETENTION_DAYS: u32 = 30;
+// Metadata con la identidad de quien hace el pedido, se registra en la auditoria
+pub const CALLER_METADATA_KEY: &str = "x-caller-id";
+// Cada cuanto WatchUsers revisa la auditoria aunque no haya cambios locales, para
+// ver los cambios hechos por otras instancias del servidor
+pub const WATCH_POLL_INTERVAL_MS: u64 = 1000;
 
 pub const MAX_T_SCHEDULING_USERS_TEST: usize = 10;
 pub const MAX_USERS_TEST: usize = 1024;
 
 pub fn initialize_logging() {
-    env_logger::builder()
-        .filter(
-            None,
-            env::var("RUST_LOG")
-                .unwrap_or_default()
-                .parse::<log::LevelFilter>()
-                .unwrap_or(DEFAULT_LEVEL_LOG),
-        )
+    initialize_logging_with_level(None);
+}
+
+// Sin level se usan las directivas de RUST_LOG tal cual
+pub fn initialize_logging_with_level(level: Option<log::LevelFilter>) {
+    env_logger::Builder::new()
+        .parse_filters(&log_filters(level, &|name| env::var(name).ok()))
         .format_timestamp(None)
         .init();
 }
 
-pub fn validate_mail(mail: &str) -> Result<(), Status> {
+// Filtros de env_logger: el nivel configurado, o si no hay, las directivas de
+// RUST_LOG (info,sqlx=warn) o el nivel por defecto. env resuelve las variables de
+// entorno, asi los tests no tocan las del proceso
+pub fn log_filters(
+    level: Option<log::LevelFilter>,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> String {
+    match level {
+        Some(level) => level.to_string(),
+        None => env("RUST_LOG")
+            .filter(|filters| !filters.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_LEVEL_LOG.to_string()),
+    }
+}
+
+// Formato de los ids que genera el servidor cuando CreateUserRequest no trae uno.
+// Ambos se ordenan por fecha de creacion, asi las paginas por id siguen el alta
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdFormat {
+    #[default]
+    UuidV7,
+    Ulid,
+}
+
+impl IdFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdFormat::UuidV7 => "uuidv7",
+            IdFormat::Ulid => "ulid",
+        }
+    }
+
+    pub fn generate(&self) -> String {
+        match self {
+            IdFormat::UuidV7 => uuid::Uuid::now_v7().to_string(),
+            IdFormat::Ulid => ulid::Ulid::new().to_string(),
+        }
+    }
+}
+
+impl FromStr for IdFormat {
+    type Err = ErrorKinsper;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "uuidv7" => Ok(IdFormat::UuidV7),
+            "ulid" => Ok(IdFormat::Ulid),
+            _ => Err(ErrorKinsper::InvalidArgument(format!(
+                "Invalid id format: {:?}",
+                format
+            ))),
+        }
+    }
+}
+
+// Entorno en el que corre el servidor. Las operaciones destructivas sobre toda la
+// tabla, como ResetUserTable, solo se permiten en development y test
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ServerEnvironment {
+    #[default]
+    Production,
+    Development,
+    Test,
+}
+
+impl ServerEnvironment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerEnvironment::Production => "production",
+            ServerEnvironment::Development => "development",
+            ServerEnvironment::Test => "test",
+        }
+    }
+
+    pub fn allows_reset(&self) -> bool {
+        matches!(
+            self,
+            ServerEnvironment::Development | ServerEnvironment::Test
+        )
+    }
+}
+
+impl FromStr for ServerEnvironment {
+    type Err = ErrorKinsper;
+
+    fn from_str(environment: &str) -> Result<Self, Self::Err> {
+        match environment {
+            "production" => Ok(ServerEnvironment::Production),
+            "development" => Ok(ServerEnvironment::Development),
+            "test" => Ok(ServerEnvironment::Test),
+            _ => Err(ErrorKinsper::InvalidArgument(format!(
+                "Invalid environment: {:?}",
+                environment
+            ))),
+        }
+    }
+}
+
+// Largo de la columna users.id
+pub const DEFAULT_ID_MAX_LENGTH: usize = 48;
+// Clase de caracteres (estilo regex) permitidos por defecto en los ids. Alcanza
+// para los ids que genera el servidor, tanto UUIDv7 como ULID
+pub const DEFAULT_ID_CHARSET: &str = "A-Za-z0-9_-";
+
+// Reglas que tiene que cumplir cualquier id que llega en un UserId
+#[derive(Debug, Clone)]
+pub struct IdPolicy {
+    max_length: usize,
+    charset: String,
+    charset_regex: regex::Regex,
+    // Solo se aceptan UUIDs en su forma canonica (minusculas y con guiones)
+    uuid_only: bool,
+}
+
+impl Default for IdPolicy {
+    fn default() -> Self {
+        IdPolicy::new()
+    }
+}
+
+impl IdPolicy {
+    pub fn new() -> Self {
+        IdPolicy {
+            max_length: DEFAULT_ID_MAX_LENGTH,
+            charset: DEFAULT_ID_CHARSET.to_string(),
+            charset_regex: Self::charset_regex(DEFAULT_ID_CHARSET)
+                .expect("the default id charset is a valid regex"),
+            uuid_only: false,
+        }
+    }
+
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    pub fn with_charset(mut self, charset: &str) -> Result<Self, ErrorKinsper> {
+        self.charset_regex = Self::charset_regex(charset)?;
+        self.charset = charset.to_string();
+        Ok(self)
+    }
+
+    pub fn with_uuid_only(mut self, uuid_only: bool) -> Self {
+        self.uuid_only = uuid_only;
+        self
+    }
+
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    pub fn charset(&self) -> &str {
+        &self.charset
+    }
+
+    pub fn uuid_only(&self) -> bool {
+        self.uuid_only
+    }
+
+    // La clase se arma con los caracteres y rangos (a-z) del charset, cada uno
+    // escapado, asi el charset no puede meter sintaxis de regex. Un '-' al principio
+    // o al final es literal. Los corchetes, la barra invertida y el '^' inicial se
+    // rechazan para que un charset escrito como regex (\w, [^...]) no se tome literal
+    fn charset_regex(charset: &str) -> Result<regex::Regex, ErrorKinsper> {
+        let invalid =
+            || ErrorKinsper::InvalidArgument(format!("Invalid id charset: {:?}", charset));
+        if charset.is_empty() || charset.starts_with('^') || charset.contains(['[', ']', '\\']) {
+            return Err(invalid());
+        }
+
+        let chars: Vec<char> = charset.chars().collect();
+        let mut class = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let start = chars[i];
+            match chars.get(i + 1..i + 3) {
+                Some(&['-', end]) => {
+                    if start > end {
+                        return Err(invalid());
+                    }
+                    class.push_str(&regex::escape(&start.to_string()));
+                    class.push('-');
+                    class.push_str(&regex::escape(&end.to_string()));
+                    i += 3;
+                }
+                _ => {
+                    class.push_str(&regex::escape(&start.to_string()));
+                    i += 1;
+                }
+            }
+        }
+
+        regex::Regex::new(&format!("^[{}]+$", class)).map_err(|_| invalid())
+    }
+
+    pub fn validate(&self, id: &str) -> Result<(), ErrorKinsper> {
+        if id.is_empty() {
+            return Err(ErrorKinsper::InvalidId(
+                "Invalid id: must not be empty".to_string(),
+            ));
+        }
+        if id.chars().count() > self.max_length {
+            return Err(ErrorKinsper::InvalidId(format!(
+                "Invalid id: must be at most {} characters long",
+                self.max_length
+            )));
+        }
+        if self.uuid_only {
+            return match uuid::Uuid::parse_str(id) {
+                Ok(uuid) if uuid.to_string() == id => Ok(()),
+                _ => Err(ErrorKinsper::InvalidId(
+                    "Invalid id: must be a lowercase hyphenated UUID".to_string(),
+                )),
+            };
+        }
+        if !self.charset_regex.is_match(id) {
+            return Err(ErrorKinsper::InvalidId(format!(
+                "Invalid id: only characters in [{}] are allowed",
+                self.charset
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Que hacer con la parte local del mail (antes del @) al normalizarlo. El dominio
+// siempre se pasa a minusculas
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MailLocalPartPolicy {
+    // Se guarda tal cual, Jhon@mail.com y jhon@mail.com son usuarios distintos
+    Preserve,
+    #[default]
+    Lowercase,
+    // Ademas descarta el sufijo +tag, jhon+news@mail.com es jhon@mail.com
+    LowercaseWithoutTag,
+}
+
+impl MailLocalPartPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailLocalPartPolicy::Preserve => "preserve",
+            MailLocalPartPolicy::Lowercase => "lowercase",
+            MailLocalPartPolicy::LowercaseWithoutTag => "lowercase-without-tag",
+        }
+    }
+}
+
+impl FromStr for MailLocalPartPolicy {
+    type Err = ErrorKinsper;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "preserve" => Ok(MailLocalPartPolicy::Preserve),
+            "lowercase" => Ok(MailLocalPartPolicy::Lowercase),
+            "lowercase-without-tag" => Ok(MailLocalPartPolicy::LowercaseWithoutTag),
+            _ => Err(ErrorKinsper::InvalidArgument(format!(
+                "Invalid mail local part policy: {:?}",
+                policy
+            ))),
+        }
+    }
+}
+
+// Forma en la que se guarda el mail, la unicidad se controla sobre este valor
+pub fn normalize_mail(mail: &str, policy: MailLocalPartPolicy) -> String {
+    let mail = mail.trim();
+    let Some((local, domain)) = mail.rsplit_once('@') else {
+        // Sin @ no es un mail valido, lo rechaza validate_mail
+        return mail.to_string();
+    };
+
+    let local = match policy {
+        MailLocalPartPolicy::Preserve => local.to_string(),
+        MailLocalPartPolicy::Lowercase => local.to_lowercase(),
+        MailLocalPartPolicy::LowercaseWithoutTag => local
+            .split_once('+')
+            .map_or(local, |(local, _)| local)
+            .to_lowercase(),
+    };
+    format!("{}@{}", local, domain.to_lowercase())
+}
+
+pub fn validate_mail(mail: &str) -> Result<(), ErrorKinsper> {
     regex::Regex::new(
-        r"^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})",
+        r"(?i)^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})",
     )
     .map_err(|_| ErrorKinsper::InternalValidationError("Error in validations.".to_string()))?
     .is_match(mail)
     .then_some(())
-    .ok_or_else(|| ErrorKinsper::InvalidEmail("Invalid email.".to_string()).into())
+    .ok_or_else(|| ErrorKinsper::InvalidEmail("Invalid email.".to_string()))
+}
+
+#[cfg(test)]
+mod mail_tests {
+    use crate::{normalize_mail, validate_mail, MailLocalPartPolicy};
+
+    #[test]
+    fn test01_when_normalize_mail_then_trims_and_lowercases_domain_by_policy() {
+        let mail = " Jhon.Doe+News@Mail.COM ";
+
+        assert_eq!(
+            normalize_mail(mail, MailLocalPartPolicy::Preserve),
+            "Jhon.Doe+News@mail.com"
+        );
+        assert_eq!(
+            normalize_mail(mail, MailLocalPartPolicy::Lowercase),
+            "jhon.doe+news@mail.com"
+        );
+        assert_eq!(
+            normalize_mail(mail, MailLocalPartPolicy::LowercaseWithoutTag),
+            "jhon.doe@mail.com"
+        );
+        assert!(validate_mail(&normalize_mail(mail, MailLocalPartPolicy::Preserve)).is_ok());
+    }
+
+    #[test]
+    fn test02_when_parse_mail_policy_given_unknown_value_then_returns_error() {
+        assert_eq!(
+            "lowercase-without-tag".parse(),
+            Ok(MailLocalPartPolicy::LowercaseWithoutTag)
+        );
+        assert!("upper".parse::<MailLocalPartPolicy>().is_err());
+    }
+}
+
+#[cfg(test)]
+mod id_tests {
+    use crate::{IdFormat, IdPolicy};
+
+    #[test]
+    fn test01_when_generate_id_then_uses_configured_format() {
+        let uuid = IdFormat::UuidV7.generate();
+        let ulid = IdFormat::Ulid.generate();
+
+        assert_eq!(uuid::Uuid::parse_str(&uuid).unwrap().get_version_num(), 7);
+        assert!(ulid::Ulid::from_string(&ulid).is_ok());
+        assert_ne!(IdFormat::Ulid.generate(), ulid);
+    }
+
+    #[test]
+    fn test02_when_parse_id_format_given_unknown_value_then_returns_error() {
+        assert_eq!("ulid".parse(), Ok(IdFormat::Ulid));
+        assert_eq!("uuidv7".parse(), Ok(IdFormat::UuidV7));
+        assert!("uuidv4".parse::<IdFormat>().is_err());
+    }
+
+    #[test]
+    fn test03_when_validate_id_then_checks_length_and_charset() {
+        let policy = IdPolicy::new().with_max_length(10);
+
+        assert!(policy.validate("test_id-1").is_ok());
+        assert!(policy.validate("").is_err());
+        assert!(policy.validate("test_id-123").is_err());
+        assert!(policy.validate("test id").is_err());
+        assert!(policy.validate("test@id").is_err());
+
+        let policy = policy.with_charset("a-z@").unwrap();
+        assert!(policy.validate("test@id").is_ok());
+        assert!(policy.validate("Test@id").is_err());
+        assert!(IdPolicy::new().with_charset("z-a").is_err());
+
+        // Los caracteres de regex se toman literales o se rechazan
+        let policy = IdPolicy::new().with_charset("a-c.*-").unwrap();
+        assert!(policy.validate("ab.*-").is_ok());
+        assert!(policy.validate("abd").is_err());
+        for charset in ["", "a]|.*|[a", "^a-z", "\\w", "a-z[0-9]"] {
+            assert!(IdPolicy::new().with_charset(charset).is_err());
+        }
+    }
+
+    #[test]
+    fn test04_when_validate_id_given_uuid_only_then_accepts_canonical_uuids() {
+        let policy = IdPolicy::new().with_uuid_only(true);
+        let uuid = IdFormat::UuidV7.generate();
+
+        assert!(policy.validate(&uuid).is_ok());
+        assert!(policy.validate(&uuid.to_uppercase()).is_err());
+        assert!(policy.validate(&uuid.replace('-', "")).is_err());
+        assert!(policy.validate(&IdFormat::Ulid.generate()).is_err());
+        assert!(IdPolicy::new().validate(&IdFormat::Ulid.generate()).is_ok());
+    }
 }
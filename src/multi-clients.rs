@@ -1,68 +1,82 @@
+use clap::Parser;
 use dotenv::dotenv;
 use futures::stream::StreamExt;
 use kinsper_rust_test::{
-    errors::ErrorKinsper, initialize_logging, MAX_T_SCHEDULING_USERS_TEST, MAX_USERS_TEST,
-    SERVER_LOCALHOST, SERVER_LOCALPORT,
+    connection::ConnectionOptions, errors::ErrorKinsper, initialize_logging,
+    MAX_T_SCHEDULING_USERS_TEST, MAX_USERS_TEST,
 };
-use rand::Rng;
 use user_service::{user_service_client::UserServiceClient, GetAllUserRequest};
 
-use crate::user_service::UserId;
-
 pub mod user_service {
     // Note: The token passed to the include_proto macro (in our case "routeguide") is
     // the name of the package declared in our .proto file, not a filename, e.g "routeguide.rs".
     tonic::include_proto!("user_service");
 }
 
+#[derive(Debug, Parser)]
+struct Options {
+    #[clap(flatten)]
+    connection: ConnectionOptions,
+}
+
 // #[tokio::main] // by default, it uses 4 threads
 #[tokio::main(flavor = "multi_thread", worker_threads = 10)]
 async fn main() -> Result<(), ErrorKinsper> {
     dotenv().ok();
     initialize_logging();
-    let addr = format!("http://{}:{}", SERVER_LOCALHOST, SERVER_LOCALPORT);
+    let opts = Options::parse();
+    let endpoint = opts.connection.endpoint()?;
+    let interceptor = opts.connection.interceptor(None)?;
 
     let fetches = futures::stream::iter((0..MAX_USERS_TEST).map(|user_id| {
-        // let user_rng_id = 10_usize;
-        let user_rng_id = rand::thread_rng().gen_range(0..MAX_USERS_TEST);
-        let client = UserServiceClient::connect(addr.clone());
+        let endpoint = endpoint.clone();
+        let interceptor = interceptor.clone();
 
         tokio::spawn(async move {
-            let request_get_user = tonic::Request::new(user_service::GetUserRequest {
-                id: Some(UserId {
-                    id: user_rng_id.to_string(),
-                }),
-            });
-
+            // Sin id, el servidor genera uno y lo devuelve con el usuario creado
             let request_create_user_1 = tonic::Request::new(user_service::CreateUserRequest {
-                id: Some(UserId {
-                    id: user_rng_id.to_string(),
-                }),
-                name: format!("John Doe A {}", user_rng_id),
-                mail: String::from("jhon@mail.com"),
+                id: None,
+                name: format!("John Doe A {}", user_id),
+                mail: format!("jhon{}@mail.com", user_id),
             });
 
-            let request_create_user_2 = tonic::Request::new(user_service::CreateUserRequest {
-                id: Some(UserId {
-                    id: user_rng_id.to_string(),
-                }),
-                name: format!("John Doe B {}", user_rng_id),
-                mail: String::from("jhon2@mail.com"),
-            });
+            if let Ok(channel) = endpoint.connect().await {
+                let mut client = UserServiceClient::with_interceptor(channel, interceptor);
+                let created = match client.create_user(request_create_user_1).await {
+                    Ok(response) => response.into_inner().user.and_then(|user| user.id),
+                    Err(_) => None,
+                };
 
-            let request_update_name_user =
-                tonic::Request::new(user_service::UpdateUserNameRequest {
-                    id: Some(UserId {
-                        id: user_rng_id.to_string(),
-                    }),
-                    name: format!("John Doe Updated by {}", user_id),
-                });
+                if let Some(id) = created {
+                    // Con un id ya usado el servidor responde ALREADY_EXISTS
+                    let request_create_user_2 =
+                        tonic::Request::new(user_service::CreateUserRequest {
+                            id: Some(id.clone()),
+                            name: format!("John Doe B {}", user_id),
+                            mail: format!("jhon{}.b@mail.com", user_id),
+                        });
+                    let _ = client.create_user(request_create_user_2).await;
 
-            if let Ok(mut client) = client.await {
-                let _ = client.get_user(request_get_user).await;
-                let _ = client.create_user(request_create_user_1).await;
-                let _ = client.create_user(request_create_user_2).await;
-                let _ = client.update_name_user(request_update_name_user).await;
+                    // Read-modify-write: el update solo se aplica si nadie modifico al
+                    // usuario desde que se leyo, sino el servidor responde ABORTED
+                    let request_get_user = tonic::Request::new(user_service::GetUserRequest {
+                        id: Some(id.clone()),
+                    });
+                    if let Ok(user) = client.get_user(request_get_user).await {
+                        let request_update_name_user =
+                            tonic::Request::new(user_service::UpdateUserNameRequest {
+                                id: Some(id),
+                                name: format!("John Doe Updated by {}", user_id),
+                                expected_version: user.into_inner().version,
+                            });
+
+                        if let Err(e) = client.update_name_user(request_update_name_user).await {
+                            if e.code() == tonic::Code::Aborted {
+                                log::info!("[REQ_ID_{}] Update aborted: {}", user_id, e.message());
+                            }
+                        }
+                    }
+                }
             }
 
             log::info!(
@@ -79,13 +93,18 @@ async fn main() -> Result<(), ErrorKinsper> {
         .collect::<Vec<_>>()
         .await;
 
-    let mut client = UserServiceClient::connect(addr)
+    let channel = endpoint
+        .connect()
         .await
         .map_err(|_| ErrorKinsper::InternalServer("Error connecting to server".to_string()))?;
+    let mut client = UserServiceClient::with_interceptor(channel, interceptor);
 
     print!("10 users from the server: ");
     let mut stream = client
-        .get_all_users(GetAllUserRequest { limit: 10 })
+        .get_all_users(GetAllUserRequest {
+            limit: 10,
+            ..Default::default()
+        })
         .await
         .map_err(|e| {
             if e.code() == tonic::Code::NotFound {
@@ -1,30 +1,184 @@
+use clap::Parser;
 use dotenv::dotenv;
-use kinsper_rust_test::data::context::Database;
+use kinsper_rust_test::config::{ServerConfig, ServerSettings};
+use kinsper_rust_test::data::{migrations, repository};
 use kinsper_rust_test::errors::ErrorKinsper;
 use kinsper_rust_test::handler_server::user_service::user_service_server::UserServiceServer;
 use kinsper_rust_test::handler_server::MyUserService;
-use kinsper_rust_test::{initialize_logging, SERVER_LOCALHOST, SERVER_LOCALPORT};
+use kinsper_rust_test::initialize_logging_with_level;
+use std::path::PathBuf;
 use tonic::transport::Server;
 
-#[tokio::main]
-async fn main() -> Result<(), ErrorKinsper> {
+// Identidad que queda en la auditoria de los mails que normaliza migrate normalize-mails
+const MAIL_NORMALIZATION_CALLER: &str = "server";
+
+#[derive(Debug, Parser)]
+struct Options {
+    // Archivo TOML con la configuracion, los flags y variables de entorno lo pisan
+    #[clap(long, env = "SERVER_CONFIG")]
+    config: Option<PathBuf>,
+    // Muestra la configuracion efectiva y termina
+    #[clap(long)]
+    print_config: bool,
+    #[clap(flatten)]
+    settings: ServerSettings,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Parser)]
+enum Command {
+    #[clap(subcommand)]
+    Migrate(MigrateCommand),
+}
+
+#[derive(Debug, Parser)]
+enum MigrateCommand {
+    Up,
+    Down,
+    Status,
+    // Normaliza los mails guardados con la politica configurada. Bloquea la tabla de
+    // usuarios mientras corre, por eso no se hace al arrancar el servidor
+    NormalizeMails,
+}
+
+async fn migrate(command: MigrateCommand, config: &ServerConfig) -> Result<(), ErrorKinsper> {
+    let db_context = migrations::connect(&config.database_url).await?;
+
+    match command {
+        MigrateCommand::Up => {
+            db_context.migrate_up().await?;
+            println!("Migrations applied successfully");
+        }
+        MigrateCommand::Down => match db_context.migrate_down().await? {
+            Some(version) => println!("Migration {} reverted successfully", version),
+            None => println!("No migrations to revert"),
+        },
+        MigrateCommand::Status => {
+            for migration in db_context.migration_status().await? {
+                println!(
+                    "{:>4} | {:<8} | {}",
+                    migration.version,
+                    if migration.applied {
+                        "applied"
+                    } else {
+                        "pending"
+                    },
+                    migration.description
+                );
+            }
+        }
+        MigrateCommand::NormalizeMails => normalize_mails(config).await?,
+    }
+
+    Ok(())
+}
+
+// Las migraciones no conocen la politica de mails, los mails guardados (incluidos
+// los de usuarios borrados) se normalizan aparte con la politica activa
+async fn normalize_mails(config: &ServerConfig) -> Result<(), ErrorKinsper> {
+    let db_context = repository::connect(&config.database_url).await?;
+    let policy = config.mail_policy.as_str();
+
+    let normalized = match db_context
+        .normalize_mails(config.mail_policy, MAIL_NORMALIZATION_CALLER)
+        .await
+    {
+        Err(ErrorKinsper::AlreadyExists(_)) => {
+            return Err(ErrorKinsper::MigrationError(format!(
+                "Two stored mails are the same with the {} policy, the duplicate has to be resolved by hand",
+                policy
+            )))
+        }
+        result => result?,
+    };
+    println!(
+        "Normalized {} stored mails with the {} policy",
+        normalized, policy
+    );
+
+    Ok(())
+}
+
+fn main() -> Result<(), ErrorKinsper> {
     dotenv().ok();
-    initialize_logging();
-
-    let database_url = std::env::var("DATABASE_URL")
-        .map_err(|_| ErrorKinsper::InvalidUri("Invalid database url".to_string()))?;
-    let db_context = Database::connect(&database_url).await?;
-    db_context.create_table().await?;
-
-    let addr = format!("{}:{}", SERVER_LOCALHOST, SERVER_LOCALPORT)
-        .parse()
-        .map_err(|_| ErrorKinsper::InvalidUri("Invalid server url".to_string()))?;
-    let user_service = MyUserService { db_context };
-    log::info!("Listening on {}", addr);
-
-    Server::builder()
-        .add_service(UserServiceServer::new(user_service))
-        .serve(addr)
+    let opts = Options::parse();
+
+    let file_settings = match &opts.config {
+        Some(path) => ServerSettings::from_file(path)?,
+        None => ServerSettings::default(),
+    };
+    let config = opts.settings.or(file_settings).resolve()?;
+
+    if opts.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
+    // Sin log_level se respetan las directivas de RUST_LOG
+    initialize_logging_with_level(config.log_level);
+
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.worker_threads)
+        .enable_all()
+        .build()
+        .map_err(|err| {
+            ErrorKinsper::InternalServer(format!("Couldn't start the tokio runtime: {}", err))
+        })?
+        .block_on(run(opts.command, config))
+}
+
+async fn run(command: Option<Command>, config: ServerConfig) -> Result<(), ErrorKinsper> {
+    if let Some(Command::Migrate(command)) = command {
+        return migrate(command, &config).await;
+    }
+
+    // Los certificados y claves se leen antes de conectar a la base, asi un error
+    // de TLS o de autenticacion se informa enseguida
+    let tls = config.tls_config()?;
+    let authenticator = config.authenticator()?;
+    let authorization_policy = config.authorization_policy()?;
+    let db_context =
+        repository::connect_with_pool_size(&config.database_url, config.pool_size).await?;
+
+    let user_service = MyUserService::new(db_context)
+        .with_mail_policy(config.mail_policy)
+        .with_id_format(config.id_format)
+        .with_id_policy(config.id_policy)
+        .with_query_limit(config.query_limit)
+        .with_stream_queue_size(config.stream_queue_size)
+        .with_authorization_policy(authorization_policy)
+        .with_environment(config.environment)
+        .with_database_name(repository::database_name(&config.database_url));
+    log::info!(
+        "Listening on {} in {} mode",
+        config.addr,
+        config.environment.as_str()
+    );
+
+    let mut server = Server::builder();
+    if let Some(tls) = tls {
+        server = server
+            .tls_config(tls)
+            .map_err(|err| ErrorKinsper::InvalidArgument(format!("Invalid TLS config: {}", err)))?;
+        log::info!(
+            "TLS enabled, client certificates required: {}",
+            config.tls_client_ca.is_some()
+        );
+    }
+
+    if authenticator.is_enabled() {
+        log::info!("Bearer token authentication and role-based authorization enabled");
+    } else {
+        log::warn!("Authentication disabled, every client can call every rpc");
+    }
+
+    server
+        .add_service(UserServiceServer::with_interceptor(
+            user_service,
+            authenticator,
+        ))
+        .serve(config.addr)
         .await
         .map_err(|err| {
             ErrorKinsper::InternalServer(format!("Server error initializing: {}", err))
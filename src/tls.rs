@@ -0,0 +1,241 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity, ServerTlsConfig};
+
+use crate::errors::ErrorKinsper;
+
+// Nombre con el que se valida el certificado del servidor si no se indica otro
+pub const DEFAULT_TLS_DOMAIN: &str = "localhost";
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, ErrorKinsper> {
+    std::fs::read(path)
+        .map_err(|err| ErrorKinsper::InvalidArgument(format!("Couldn't read {:?}: {}", path, err)))
+}
+
+// Certificado y clave del servidor. Con client_ca ademas se exige que el cliente
+// presente un certificado firmado por esa CA (mTLS)
+pub fn server_tls_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<ServerTlsConfig, ErrorKinsper> {
+    let mut tls =
+        ServerTlsConfig::new().identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+    if let Some(client_ca) = client_ca {
+        tls = tls.client_ca_root(Certificate::from_pem(read_pem(client_ca)?));
+    }
+    Ok(tls)
+}
+
+// Opciones de conexion compartidas por client y multi-clients
+#[derive(Debug, Clone, Args)]
+pub struct ClientTlsOptions {
+    // CA con la que se valida el certificado del servidor, habilita TLS
+    #[arg(long, env = "CLIENT_TLS_CA", global = true)]
+    pub tls_ca: Option<PathBuf>,
+    // Certificado y clave del cliente, para servidores con mTLS
+    #[arg(long, env = "CLIENT_TLS_CERT", requires = "tls_key", global = true)]
+    pub tls_cert: Option<PathBuf>,
+    #[arg(long, env = "CLIENT_TLS_KEY", requires = "tls_cert", global = true)]
+    pub tls_key: Option<PathBuf>,
+    // Nombre esperado en el certificado del servidor
+    #[arg(long, env = "CLIENT_TLS_DOMAIN", default_value = DEFAULT_TLS_DOMAIN, global = true)]
+    pub tls_domain: String,
+}
+
+impl ClientTlsOptions {
+    pub fn tls_config(&self) -> Result<Option<ClientTlsConfig>, ErrorKinsper> {
+        let Some(ca) = &self.tls_ca else {
+            if self.tls_cert.is_some() {
+                return Err(ErrorKinsper::InvalidArgument(
+                    "The client certificate requires --tls-ca".to_string(),
+                ));
+            }
+            return Ok(None);
+        };
+
+        let mut tls = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(read_pem(ca)?))
+            .domain_name(self.tls_domain.clone());
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            tls = tls.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+        }
+        Ok(Some(tls))
+    }
+
+    // Endpoint http o https segun se haya configurado TLS
+    pub fn endpoint(&self, host: &str, port: u16) -> Result<Endpoint, ErrorKinsper> {
+        let tls = self.tls_config()?;
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        let endpoint = Endpoint::from_shared(format!("{}://{}:{}", scheme, host, port))
+            .map_err(|_| ErrorKinsper::InvalidUri("Invalid server url".to_string()))?;
+        match tls {
+            Some(tls) => endpoint.tls_config(tls).map_err(|err| {
+                ErrorKinsper::InvalidArgument(format!("Invalid TLS config: {}", err))
+            }),
+            None => Ok(endpoint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tls_tests {
+    use std::path::{Path, PathBuf};
+
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Server;
+    use tonic::Request;
+
+    use crate::data::memory::InMemoryDatabase;
+    use crate::handler_server::user_service::user_service_client::UserServiceClient;
+    use crate::handler_server::user_service::user_service_server::UserServiceServer;
+    use crate::handler_server::user_service::{GetUserRequest, UserId};
+    use crate::handler_server::MyUserService;
+    use crate::tls::{server_tls_config, ClientTlsOptions, DEFAULT_TLS_DOMAIN};
+
+    // CA, certificado de servidor para localhost y certificado de cliente, todos
+    // generados para el test
+    struct Certs {
+        dir: TempDir,
+    }
+
+    impl Certs {
+        fn generate() -> Self {
+            let dir = TempDir::new().unwrap();
+
+            let mut ca_params = CertificateParams::new(vec![]);
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = Certificate::from_params(ca_params).unwrap();
+            std::fs::write(dir.path().join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+
+            for (name, subject_alt_names) in [
+                ("server", vec![DEFAULT_TLS_DOMAIN.to_string()]),
+                ("client", vec!["client".to_string()]),
+            ] {
+                let cert =
+                    Certificate::from_params(CertificateParams::new(subject_alt_names)).unwrap();
+                std::fs::write(
+                    dir.path().join(format!("{}.pem", name)),
+                    cert.serialize_pem_with_signer(&ca).unwrap(),
+                )
+                .unwrap();
+                std::fs::write(
+                    dir.path().join(format!("{}.key", name)),
+                    cert.serialize_private_key_pem(),
+                )
+                .unwrap();
+            }
+            Certs { dir }
+        }
+
+        fn path(&self, file: &str) -> PathBuf {
+            self.dir.path().join(file)
+        }
+
+        fn client_options(&self, with_identity: bool) -> ClientTlsOptions {
+            ClientTlsOptions {
+                tls_ca: Some(self.path("ca.pem")),
+                tls_cert: with_identity.then(|| self.path("client.pem")),
+                tls_key: with_identity.then(|| self.path("client.key")),
+                tls_domain: DEFAULT_TLS_DOMAIN.to_string(),
+            }
+        }
+    }
+
+    async fn serve_tls(certs: &Certs, client_ca: Option<&Path>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let tls = server_tls_config(
+            &certs.path("server.pem"),
+            &certs.path("server.key"),
+            client_ca,
+        )
+        .unwrap();
+
+        let server = Server::builder()
+            .tls_config(tls)
+            .unwrap()
+            .add_service(UserServiceServer::new(MyUserService::new(
+                InMemoryDatabase::new(),
+            )))
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        tokio::spawn(server);
+        port
+    }
+
+    // NOT_FOUND significa que el pedido llego al servicio
+    async fn get_user(options: &ClientTlsOptions, port: u16) -> Result<tonic::Code, String> {
+        let channel = options
+            .endpoint("127.0.0.1", port)
+            .map_err(|err| format!("{:?}", err))?
+            .connect()
+            .await
+            .map_err(|err| err.to_string())?;
+        let status = UserServiceClient::new(channel)
+            .get_user(Request::new(GetUserRequest {
+                id: Some(UserId {
+                    id: "tls_id".to_string(),
+                }),
+            }))
+            .await
+            .unwrap_err();
+        Ok(status.code())
+    }
+
+    #[tokio::test]
+    async fn test01_when_server_uses_tls_then_only_clients_trusting_its_ca_connect() {
+        let certs = Certs::generate();
+        let port = serve_tls(&certs, None).await;
+
+        assert_eq!(
+            get_user(&certs.client_options(false), port).await,
+            Ok(tonic::Code::NotFound)
+        );
+
+        let plaintext = ClientTlsOptions {
+            tls_ca: None,
+            ..certs.client_options(false)
+        };
+        assert_ne!(get_user(&plaintext, port).await, Ok(tonic::Code::NotFound));
+
+        let other_domain = ClientTlsOptions {
+            tls_domain: "other.host".to_string(),
+            ..certs.client_options(false)
+        };
+        assert!(get_user(&other_domain, port).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test02_when_server_uses_mtls_then_requires_client_certificate() {
+        let certs = Certs::generate();
+        let port = serve_tls(&certs, Some(&certs.path("ca.pem"))).await;
+
+        assert_eq!(
+            get_user(&certs.client_options(true), port).await,
+            Ok(tonic::Code::NotFound)
+        );
+        assert_ne!(
+            get_user(&certs.client_options(false), port).await,
+            Ok(tonic::Code::NotFound)
+        );
+    }
+
+    #[test]
+    fn test03_when_client_certificate_without_ca_then_returns_error() {
+        let options = ClientTlsOptions {
+            tls_ca: None,
+            tls_cert: Some(PathBuf::from("client.pem")),
+            tls_key: Some(PathBuf::from("client.key")),
+            tls_domain: DEFAULT_TLS_DOMAIN.to_string(),
+        };
+
+        assert!(options.tls_config().is_err());
+        assert!(
+            server_tls_config(Path::new("missing.pem"), Path::new("missing.key"), None).is_err()
+        );
+    }
+}
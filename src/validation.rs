@@ -0,0 +1,347 @@
+use crate::errors::rpc::bad_request::FieldViolation;
+use crate::errors::ErrorKinsper;
+use crate::handler_server::user_service::{
+    CreateUserRequest, DeleteUserRequest, GetAllUserRequest, GetUserByMailRequest, GetUserRequest,
+    ListAuditEventsRequest, PurgeDeletedUsersRequest, ResetUserTableRequest, RestoreUserRequest,
+    SearchUsersRequest, UpdateUserMailRequest, UpdateUserNameRequest, UserId, WatchUsersRequest,
+};
+use crate::{normalize_mail, validate_mail, IdPolicy, MailLocalPartPolicy, DEFAULT_ID_MAX_LENGTH};
+
+// Largos de las columnas name y mail de users
+pub const NAME_MAX_LENGTH: usize = 256;
+pub const MAIL_MAX_LENGTH: usize = 256;
+// Largo maximo de los filtros de texto y de la confirmacion
+pub const FILTER_MAX_LENGTH: usize = 256;
+// Los page tokens son el hex de ["<clave de orden>","<id>"] en UTF-8, el mas largo
+// es el de SearchUsers ordenado por name o mail. Cada caracter ocupa hasta 4 bytes
+// y cada byte 2 caracteres en hex
+const SORT_KEY_MAX_LENGTH: usize = if NAME_MAX_LENGTH > MAIL_MAX_LENGTH {
+    NAME_MAX_LENGTH
+} else {
+    MAIL_MAX_LENGTH
+};
+const PAGE_TOKEN_FRAMING: usize = r#"["",""]"#.len();
+pub const PAGE_TOKEN_MAX_LENGTH: usize =
+    2 * (4 * (SORT_KEY_MAX_LENGTH + DEFAULT_ID_MAX_LENGTH) + PAGE_TOKEN_FRAMING);
+
+// Junta todas las violaciones de un pedido en vez de cortar en la primera, asi el
+// cliente puede corregir todos los campos de una vez
+pub struct RequestValidator<'a> {
+    id_policy: &'a IdPolicy,
+    mail_policy: MailLocalPartPolicy,
+    violations: Vec<FieldViolation>,
+}
+
+impl<'a> RequestValidator<'a> {
+    pub fn new(id_policy: &'a IdPolicy, mail_policy: MailLocalPartPolicy) -> Self {
+        RequestValidator {
+            id_policy,
+            mail_policy,
+            violations: vec![],
+        }
+    }
+
+    pub fn validate<T: Validate>(mut self, request: &T) -> Result<(), ErrorKinsper> {
+        request.validate(&mut self);
+        if self.violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ErrorKinsper::InvalidRequest(self.violations))
+        }
+    }
+
+    fn violation(&mut self, field: &str, description: String) {
+        self.violations.push(FieldViolation {
+            field: field.to_string(),
+            description,
+        });
+    }
+
+    pub fn required_id(&mut self, field: &str, id: &Option<UserId>) {
+        match id {
+            Some(id) => self.id(field, &id.id),
+            None => self.violation(field, "Invalid id: missing".to_string()),
+        }
+    }
+
+    // Vacio o ausente es valido, por ejemplo en el alta donde el servidor genera el id
+    pub fn optional_id(&mut self, field: &str, id: &str) {
+        if !id.is_empty() {
+            self.id(field, id);
+        }
+    }
+
+    fn id(&mut self, field: &str, id: &str) {
+        if let Err(ErrorKinsper::InvalidId(description)) = self.id_policy.validate(id) {
+            self.violation(field, description);
+        }
+    }
+
+    pub fn name(&mut self, field: &str, name: &str) {
+        if name.trim().is_empty() {
+            self.violation(field, "Invalid name: must not be empty".to_string());
+            return;
+        }
+        if name.trim() != name {
+            self.violation(
+                field,
+                "Invalid name: must not start or end with whitespace".to_string(),
+            );
+        }
+        self.text(field, "name", name, NAME_MAX_LENGTH);
+    }
+
+    // El mail se valida ya normalizado, que es como se guarda
+    pub fn mail(&mut self, field: &str, mail: &str) {
+        let mail = normalize_mail(mail, self.mail_policy);
+        if mail.is_empty() {
+            self.violation(field, "Invalid mail: must not be empty".to_string());
+            return;
+        }
+        if mail.chars().count() > MAIL_MAX_LENGTH {
+            self.violation(
+                field,
+                format!(
+                    "Invalid mail: must be at most {} characters long",
+                    MAIL_MAX_LENGTH
+                ),
+            );
+        } else if validate_mail(&mail).is_err() {
+            self.violation(
+                field,
+                "Invalid mail: must be a valid email address".to_string(),
+            );
+        }
+    }
+
+    // Texto que el cliente tiene que repetir para confirmar una operacion destructiva
+    pub fn confirmation(&mut self, field: &str, value: &str) {
+        if value.is_empty() {
+            self.violation(field, "Invalid confirmation: must not be empty".to_string());
+            return;
+        }
+        self.text(field, "confirmation", value, FILTER_MAX_LENGTH);
+    }
+
+    // Filtros opcionales: solo se controla el largo y los caracteres
+    pub fn filter(&mut self, field: &str, value: &str) {
+        self.text(field, field, value, FILTER_MAX_LENGTH);
+    }
+
+    // Tokens opacos de paginacion, el handler controla que decodifiquen
+    pub fn page_token(&mut self, field: &str, value: &str) {
+        self.text(field, field, value, PAGE_TOKEN_MAX_LENGTH);
+    }
+
+    fn text(&mut self, field: &str, label: &str, value: &str, max_length: usize) {
+        if value.chars().count() > max_length {
+            self.violation(
+                field,
+                format!(
+                    "Invalid {}: must be at most {} characters long",
+                    label, max_length
+                ),
+            );
+        }
+        if value.chars().any(char::is_control) {
+            self.violation(
+                field,
+                format!("Invalid {}: must not contain control characters", label),
+            );
+        }
+    }
+}
+
+// Reglas de cada mensaje de entrada. Los nombres de campo son los del .proto
+pub trait Validate {
+    fn validate(&self, validator: &mut RequestValidator);
+}
+
+impl Validate for GetUserRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.required_id("id", &self.id);
+    }
+}
+
+impl Validate for GetUserByMailRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.mail("mail", &self.mail);
+    }
+}
+
+impl Validate for GetAllUserRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.page_token("page_token", &self.page_token);
+    }
+}
+
+impl Validate for SearchUsersRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.filter("name_contains", &self.name_contains);
+        validator.filter("name_prefix", &self.name_prefix);
+        validator.filter("mail", &self.mail);
+        validator.filter("mail_domain", &self.mail_domain);
+        validator.filter("query", &self.query);
+        validator.page_token("page_token", &self.page_token);
+    }
+}
+
+impl Validate for CreateUserRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        if let Some(id) = &self.id {
+            validator.optional_id("id", &id.id);
+        }
+        validator.name("name", &self.name);
+        validator.mail("mail", &self.mail);
+    }
+}
+
+impl Validate for UpdateUserNameRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.required_id("id", &self.id);
+        validator.name("name", &self.name);
+    }
+}
+
+impl Validate for UpdateUserMailRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.required_id("id", &self.id);
+        validator.mail("mail", &self.mail);
+    }
+}
+
+impl Validate for DeleteUserRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.required_id("id", &self.id);
+    }
+}
+
+impl Validate for RestoreUserRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.required_id("id", &self.id);
+    }
+}
+
+impl Validate for PurgeDeletedUsersRequest {
+    fn validate(&self, _validator: &mut RequestValidator) {}
+}
+
+impl Validate for ResetUserTableRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.confirmation("confirmation", &self.confirmation);
+    }
+}
+
+impl Validate for ListAuditEventsRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.optional_id("user_id", &self.user_id);
+        validator.page_token("page_token", &self.page_token);
+    }
+}
+
+impl Validate for WatchUsersRequest {
+    fn validate(&self, validator: &mut RequestValidator) {
+        validator.optional_id("user_id", &self.user_id);
+        validator.page_token("resume_token", &self.resume_token);
+    }
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use crate::errors::ErrorKinsper;
+    use crate::handler_server::user_service::{
+        CreateUserRequest, SearchUsersRequest, UpdateUserNameRequest, UserId,
+    };
+    use crate::validation::RequestValidator;
+    use crate::{IdPolicy, MailLocalPartPolicy};
+
+    fn violations<T: super::Validate>(request: &T) -> Vec<(String, String)> {
+        let id_policy = IdPolicy::new();
+        match RequestValidator::new(&id_policy, MailLocalPartPolicy::default()).validate(request) {
+            Ok(()) => vec![],
+            Err(ErrorKinsper::InvalidRequest(violations)) => violations
+                .into_iter()
+                .map(|violation| (violation.field, violation.description))
+                .collect(),
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test01_when_validate_given_valid_request_then_has_no_violations() {
+        let request = CreateUserRequest {
+            id: None,
+            name: "John Doe".to_string(),
+            mail: " John@Mail.com ".to_string(),
+        };
+
+        assert!(violations(&request).is_empty());
+    }
+
+    #[test]
+    fn test02_when_validate_given_invalid_fields_then_lists_every_violation() {
+        let request = CreateUserRequest {
+            id: Some(UserId {
+                id: "with space".to_string(),
+            }),
+            name: " John\tDoe".to_string(),
+            mail: "not-a-mail".to_string(),
+        };
+
+        let violations = violations(&request);
+
+        let fields: Vec<&str> = violations.iter().map(|(field, _)| field.as_str()).collect();
+        assert_eq!(fields, vec!["id", "name", "name", "mail"]);
+        assert_eq!(
+            violations[1].1,
+            "Invalid name: must not start or end with whitespace"
+        );
+        assert_eq!(
+            violations[2].1,
+            "Invalid name: must not contain control characters"
+        );
+    }
+
+    #[test]
+    fn test03_when_validate_given_empty_or_long_name_then_returns_violation() {
+        let request = |name: String| UpdateUserNameRequest {
+            id: Some(UserId {
+                id: "id".to_string(),
+            }),
+            name,
+            expected_version: 0,
+        };
+
+        assert_eq!(
+            violations(&request("   ".to_string())),
+            vec![(
+                "name".to_string(),
+                "Invalid name: must not be empty".to_string()
+            )]
+        );
+        assert_eq!(
+            violations(&request("a".repeat(257))),
+            vec![(
+                "name".to_string(),
+                "Invalid name: must be at most 256 characters long".to_string()
+            )]
+        );
+        assert!(violations(&request("a".repeat(256))).is_empty());
+    }
+
+    #[test]
+    fn test04_when_validate_given_control_characters_in_filters_then_returns_violation() {
+        let request = SearchUsersRequest {
+            name_contains: "jo\u{0}hn".to_string(),
+            query: "a".repeat(300),
+            ..Default::default()
+        };
+
+        let fields: Vec<String> = violations(&request)
+            .into_iter()
+            .map(|(field, _)| field)
+            .collect();
+
+        assert_eq!(fields, vec!["name_contains", "query"]);
+    }
+}